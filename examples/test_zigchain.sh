@@ -58,16 +58,16 @@ echo "============"
 if command -v jq &> /dev/null; then
     echo ""
     echo "Top 3 Endpoints by Flakiness Score:"
-    jq -r '.[] | "\(.flakiness_score | tonumber | round) - \(.endpoint)"' "${OUTPUT_FILE}" | \
+    jq -r '.reports[] | "\(.flakiness_score | tonumber | round) - \(.endpoint)"' "${OUTPUT_FILE}" | \
         sort -n | head -3
     
     echo ""
     echo "Endpoints with >30 flakiness score (Warning):"
-    jq -r '.[] | select(.flakiness_score > 30) | "⚠️  \(.endpoint) - Score: \(.flakiness_score)"' "${OUTPUT_FILE}"
+    jq -r '.reports[] | select(.flakiness_score > 30) | "⚠️  \(.endpoint) - Score: \(.flakiness_score)"' "${OUTPUT_FILE}"
     
     echo ""
     echo "Best performing query paths:"
-    jq -r '.[] | .queries[] | "\(.failure_rate * 100 | round)% failure - \(.query)"' "${OUTPUT_FILE}" | \
+    jq -r '.reports[] | .queries[] | "\(.failure_rate * 100 | round)% failure - \(.query)"' "${OUTPUT_FILE}" | \
         sort -n | head -5
 else
     echo "Install 'jq' for detailed analysis: sudo apt install jq"
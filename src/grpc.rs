@@ -0,0 +1,146 @@
+//! Probes for `--grpc` mode. Queries map onto standard gRPC services that
+//! Cosmos nodes expose on their gRPC port (usually 9090):
+//!
+//! - `health` calls `grpc.health.v1.Health/Check` for the whole server, and
+//!   `health?service=<name>` checks a single service.
+//! - `reflection` lists services via `grpc.reflection.v1alpha.ServerReflection`.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use reqwest::header::HeaderMap;
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::{Code, Request, Status};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use tonic_reflection::pb::v1alpha::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::v1alpha::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::v1alpha::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::v1alpha::{ServerReflectionRequest, ServerReflectionResponse};
+
+use crate::{query_method, FailureKind, QueryResponse};
+
+/// Used instead of the REST defaults when `--queries` is not given.
+pub const DEFAULT_QUERIES: &[&str] = &["health", "reflection"];
+
+pub fn validate_query(query: &str) -> Result<(), String> {
+    match query_method(query) {
+        "health" | "reflection" => Ok(()),
+        other => Err(format!(
+            "Unsupported gRPC query \"{}\": use health, health?service=<name> or reflection",
+            other
+        )),
+    }
+}
+
+/// A lazily connecting channel; `https://` targets use TLS with the webpki roots
+/// plus `ca_cert`, if given. `local_address` pins the address family.
+pub fn channel(
+    endpoint: &str,
+    timeout: Duration,
+    ca_cert: Option<&[u8]>,
+    local_address: Option<IpAddr>,
+) -> Result<Channel, String> {
+    let invalid = |e: tonic::transport::Error| format!("Invalid gRPC endpoint {}: {}", endpoint, e);
+    let mut target = Endpoint::from_shared(endpoint.to_string())
+        .map_err(invalid)?
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .local_address(local_address);
+    if endpoint.starts_with("https://") {
+        let mut tls = ClientTlsConfig::new().with_webpki_roots();
+        if let Some(pem) = ca_cert {
+            tls = tls.ca_certificate(Certificate::from_pem(pem));
+        }
+        target = target.tls_config(tls).map_err(invalid)?;
+    }
+    Ok(target.connect_lazy())
+}
+
+/// Runs one gRPC query. Unary calls have no separate body download, so TTFB
+/// equals latency.
+pub async fn probe(
+    channel: Channel,
+    query: &str,
+    headers: &HeaderMap,
+    timeout: Duration,
+) -> Result<QueryResponse, FailureKind> {
+    let start = Instant::now();
+    match query_method(query) {
+        "health" => check_health(channel, health_service(query), headers, timeout).await?,
+        _ => list_services(channel, headers, timeout).await?,
+    }
+    let latency = start.elapsed();
+    Ok(QueryResponse { latency, ttfb: latency, status: None, body: None, bytes: 0, retried: false, redirect: None })
+}
+
+/// The `service` parameter of `health?service=<name>`; empty means the whole server.
+fn health_service(query: &str) -> String {
+    let params = query.split_once('?').map_or("", |(_, params)| params);
+    params
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("service="))
+        .unwrap_or_default()
+        .to_string()
+}
+
+async fn check_health(
+    channel: Channel,
+    service: String,
+    headers: &HeaderMap,
+    timeout: Duration,
+) -> Result<(), FailureKind> {
+    let response = HealthClient::new(channel)
+        .check(request(HealthCheckRequest { service }, headers, timeout))
+        .await
+        .map_err(|status| failure_kind(&status))?;
+    if response.into_inner().status == ServingStatus::Serving as i32 {
+        Ok(())
+    } else {
+        Err(FailureKind::InvalidResponse)
+    }
+}
+
+async fn list_services(channel: Channel, headers: &HeaderMap, timeout: Duration) -> Result<(), FailureKind> {
+    let list = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut stream = ServerReflectionClient::new(channel)
+        .server_reflection_info(request(tokio_stream::iter([list]), headers, timeout))
+        .await
+        .map_err(|status| failure_kind(&status))?
+        .into_inner();
+    let reply = stream.message().await.map_err(|status| failure_kind(&status))?;
+    match reply {
+        Some(ServerReflectionResponse {
+            message_response: Some(MessageResponse::ListServicesResponse(list)),
+            ..
+        }) if !list.service.is_empty() => Ok(()),
+        Some(ServerReflectionResponse {
+            message_response: Some(MessageResponse::ErrorResponse(_)),
+            ..
+        }) => Err(FailureKind::RpcError),
+        _ => Err(FailureKind::InvalidResponse),
+    }
+}
+
+/// Wraps a message with the `--header` values as metadata and a gRPC deadline.
+fn request<T>(message: T, headers: &HeaderMap, timeout: Duration) -> Request<T> {
+    let mut request = Request::new(message);
+    *request.metadata_mut() = MetadataMap::from_headers(headers.clone());
+    request.set_timeout(timeout);
+    request
+}
+
+fn failure_kind(status: &Status) -> FailureKind {
+    match status.code() {
+        // The client-side timeout surfaces as `Cancelled`.
+        Code::DeadlineExceeded | Code::Cancelled => FailureKind::Timeout,
+        Code::Unavailable => FailureKind::Connect,
+        Code::ResourceExhausted => FailureKind::RateLimited { retry_after: None },
+        _ => FailureKind::RpcError,
+    }
+}
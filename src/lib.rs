@@ -0,0 +1,3445 @@
+//! The flake detection engine behind the `cosmos-flake-detector` binary. Build a
+//! [`TestConfig`], check it with [`TestConfig::validate`] and pass it to
+//! [`run_test`] to get one [`EndpointReport`] per endpoint (two with
+//! [`IpVersion::Both`], one per address family).
+
+mod grpc;
+mod tcp;
+mod websocket;
+
+pub use grpc::DEFAULT_QUERIES as GRPC_DEFAULT_QUERIES;
+pub use tcp::DEFAULT_QUERIES as TCP_DEFAULT_QUERIES;
+pub use websocket::WebSocketReport;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use colored::Colorize;
+use futures_util::future::join_all;
+use hdrhistogram::Histogram;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Client, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tokio_native_tls::native_tls;
+use tower::{Layer, Service};
+use tracing::{debug, info, warn, Instrument};
+
+/// The Tendermint RPC queries tested when none are given.
+pub const DEFAULT_QUERIES: &str = "health,status,abci_info,net_info,genesis";
+
+/// Settings shared by every worker of a run.
+#[derive(Debug, Clone)]
+pub struct TestConfig {
+    pub endpoints: Vec<String>,
+    pub queries: Vec<String>,
+    pub duration: Duration,
+    /// Stop each query once this many measured requests have been sent, instead
+    /// of after `duration`.
+    pub max_requests: Option<u64>,
+    pub warmup: Duration,
+    /// Idle time between one query (or phase) of an endpoint and the next, so
+    /// a heavy query does not leave the next one measuring its aftermath.
+    pub query_cooldown: Duration,
+    pub concurrency: usize,
+    /// Per-query worker counts, looked up like `query_timeouts`. Ignored with
+    /// `interleave`, where every worker sends every query.
+    pub query_concurrency: HashMap<String, usize>,
+    /// Queries never sent to an endpoint (`--exclude`), keyed by endpoint URL.
+    pub excluded_queries: HashMap<String, Vec<String>>,
+    pub timeout: Duration,
+    pub query_timeouts: HashMap<String, Duration>,
+    /// p99 latency targets, looked up like `query_timeouts`.
+    pub slo_p99: HashMap<String, Duration>,
+    /// Apdex threshold T (`--apdex-target`); `None` skips the Apdex score.
+    pub apdex_target: Option<Duration>,
+    /// Substrings a successful response body must contain (`--expect`), looked
+    /// up like `query_timeouts`. A body missing any of them is an `assertion_failed`.
+    pub expectations: HashMap<String, Vec<String>>,
+    /// Successful responses below which a query is flagged `low_confidence`.
+    pub min_samples: u64,
+    /// Latency percentiles, each in (0, 100], reported in `QueryResult::percentiles`.
+    pub percentiles: Vec<f64>,
+    pub protocol: Protocol,
+    /// Verb for REST requests (`--method`).
+    pub method: RequestMethod,
+    /// Added to the URL of every HTTP request (`--query-params`), after any
+    /// parameters already on the endpoint or query.
+    pub query_params: Vec<(String, String)>,
+    /// JSON-RPC calls packed into each batch POST (`--batch`); `None` sends single calls.
+    pub batch: Option<usize>,
+    pub grpc: bool,
+    /// Time bare TCP handshakes instead of sending queries (`--tcp-only`).
+    pub tcp_only: bool,
+    pub interval: Duration,
+    pub retries: u32,
+    /// Share of answered requests that may need a retry before the excess
+    /// counts against the score as failures (`--retry-budget`).
+    pub retry_budget: f64,
+    pub jitter: f64,
+    /// Share of scheduled requests actually sent (`--sample-rate`). A skipped
+    /// request still waits out its `interval`, so the cadence is kept and the
+    /// load drops by the same share.
+    pub sample_rate: f64,
+    /// Drawn at random unless `--seed` was given; worker `n` uses `seed + n`.
+    pub seed: u64,
+    pub sequential: bool,
+    /// Test all queries at once, each worker cycling through them, instead of one after another.
+    pub interleave: bool,
+    /// Test all queries at once, each with its own pool of workers. Multiplies
+    /// the load on the endpoint by the number of queries.
+    pub parallel_queries: bool,
+    pub validators: HashMap<String, ResponseValidator>,
+    pub headers: HeaderMap,
+    pub weights: ScoreWeights,
+    pub export_histogram: bool,
+    pub bucket: Option<Duration>,
+    pub respect_retry_after: bool,
+    pub websocket: bool,
+    /// Custom request bodies from `--payload-file`, keyed by query name.
+    pub payloads: HashMap<String, Value>,
+    /// Progress bars are only drawn when stdout is a terminal.
+    pub progress: Option<MultiProgress>,
+    /// Set to end the run early. Workers finish their in-flight request and
+    /// stop, and whatever was measured so far is still reported.
+    pub stop: Arc<AtomicBool>,
+    /// Caps in-flight requests across every endpoint and query (`--max-concurrency-global`).
+    pub global_limit: Option<GlobalLimit>,
+    /// Prints successful requests slower than a threshold as they happen (`--slow-request-ms`).
+    pub slow_requests: Option<SlowRequestLog>,
+    /// Also merge every endpoint's per-query histograms into one synthetic
+    /// `ALL` report, as if the pool were a single load-balanced endpoint (`--aggregate`).
+    pub aggregate: bool,
+    /// Appends every failed measured request to a file (`--record-failures`).
+    pub failure_log: Option<FailureLog>,
+    /// Routes HTTP queries through a proxy. WebSocket subscriptions still connect directly.
+    pub proxy: Option<Proxy>,
+    /// Skips TLS certificate verification (`--insecure`). Not supported for gRPC.
+    pub accept_invalid_certs: bool,
+    /// An extra trusted root CA, PEM-encoded (`--ca-cert`).
+    pub ca_cert: Option<Vec<u8>>,
+    /// Applies to HTTP and gRPC queries and connection sampling; WebSocket
+    /// subscriptions connect over whichever family the system picks.
+    pub ip_version: IpVersion,
+    /// Open a new connection for every HTTP request instead of reusing pooled
+    /// ones, so each latency includes the TCP and TLS handshakes.
+    pub fresh_connections: bool,
+    /// Follow HTTP redirects, up to `max_redirects` hops; a longer chain
+    /// fails the request. Unfollowed, a 3xx response is a `redirect` failure.
+    pub follow_redirects: bool,
+    pub max_redirects: usize,
+    /// Ends each query phase early once the endpoint has proven healthy.
+    pub stop_on_healthy: Option<HealthyExit>,
+    /// `--adaptive`: the failure rate over the last `ADAPTIVE_WINDOW` above which a
+    /// query's effective concurrency is halved; below it, one worker is added
+    /// back. `None` keeps every worker sending.
+    pub adaptive_threshold: Option<f64>,
+    /// Region tags by endpoint URL, copied into each endpoint's report.
+    pub regions: HashMap<String, String>,
+    /// Endpoints dropped by [`preflight`] before the run, copied into the
+    /// [`RunReport`]. They are not in `endpoints`.
+    pub skipped_endpoints: Vec<SkippedEndpoint>,
+    /// Print nothing while testing. Errors still go to stderr, but plain.
+    pub quiet: bool,
+}
+
+/// Mirrors the CLI defaults. `endpoints` is empty and must be filled in.
+impl Default for TestConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            queries: DEFAULT_QUERIES.split(',').map(String::from).collect(),
+            duration: Duration::from_secs(60),
+            max_requests: None,
+            warmup: Duration::ZERO,
+            query_cooldown: Duration::ZERO,
+            concurrency: 10,
+            query_concurrency: HashMap::new(),
+            excluded_queries: HashMap::new(),
+            timeout: Duration::from_secs(5),
+            query_timeouts: HashMap::new(),
+            slo_p99: HashMap::new(),
+            apdex_target: None,
+            expectations: HashMap::new(),
+            min_samples: 100,
+            percentiles: vec![50.0, 95.0, 99.0],
+            protocol: Protocol::Rest,
+            method: RequestMethod::Get,
+            query_params: Vec::new(),
+            batch: None,
+            grpc: false,
+            tcp_only: false,
+            interval: Duration::from_millis(100),
+            retries: 0,
+            retry_budget: 0.05,
+            jitter: 0.0,
+            sample_rate: 1.0,
+            seed: rand::random(),
+            sequential: false,
+            interleave: false,
+            parallel_queries: false,
+            validators: response_validators(),
+            headers: HeaderMap::new(),
+            weights: ScoreWeights {
+                failure: 0.7,
+                latency: 0.3,
+                stability: 0.0,
+                latency_threshold_ms: 1000.0,
+                latency_metric: LatencyMetric::P99,
+                transport_failure: 1.0,
+                application_failure: 1.0,
+            },
+            export_histogram: false,
+            bucket: None,
+            respect_retry_after: false,
+            websocket: false,
+            payloads: HashMap::new(),
+            progress: None,
+            stop: Arc::default(),
+            global_limit: None,
+            slow_requests: None,
+            aggregate: false,
+            failure_log: None,
+            proxy: None,
+            accept_invalid_certs: false,
+            ca_cert: None,
+            ip_version: IpVersion::Auto,
+            fresh_connections: false,
+            follow_redirects: true,
+            max_redirects: 10,
+            stop_on_healthy: None,
+            adaptive_threshold: None,
+            regions: HashMap::new(),
+            skipped_endpoints: Vec::new(),
+            quiet: false,
+        }
+    }
+}
+
+impl TestConfig {
+    /// Checks the settings `run_test` relies on: score weights, the jitter range,
+    /// the CA certificate, in gRPC mode the queries and endpoint URLs, and with
+    /// `tcp_only` the `host:port` endpoints.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.endpoints.is_empty() {
+            return Err("No endpoints to test".to_string());
+        }
+        self.weights.validate()?;
+        if let Some(exit) = &self.stop_on_healthy {
+            exit.validate()?;
+        }
+        for query in &self.queries {
+            expand_query(query, 0)?;
+        }
+        if let Some(p) = self.percentiles.iter().find(|p| !(**p > 0.0 && **p <= 100.0)) {
+            return Err(format!("--percentiles must be above 0 and at most 100, got {}", p));
+        }
+        if let Some(batch) = self.batch {
+            if batch == 0 {
+                return Err("--batch must be at least 1".to_string());
+            }
+            if self.grpc || self.protocol != Protocol::Jsonrpc {
+                return Err("--batch needs --protocol jsonrpc".to_string());
+            }
+        }
+        if self.slow_requests.as_ref().is_some_and(|log| log.threshold.is_zero()) {
+            return Err("--slow-request-ms must be at least 1".to_string());
+        }
+        if self.global_limit.as_ref().is_some_and(|limit| limit.max == 0) {
+            return Err("--max-concurrency-global must be at least 1".to_string());
+        }
+        if let Some(threshold) = self.adaptive_threshold
+            && !(0.0..1.0).contains(&threshold)
+        {
+            return Err(format!("--adaptive-threshold must be at least 0 and below 1, got {}", threshold));
+        }
+        if self.method != RequestMethod::Get && (self.grpc || self.protocol != Protocol::Rest) {
+            return Err("--method needs --protocol rest".to_string());
+        }
+        if self.grpc && !self.query_params.is_empty() {
+            return Err("--query-params is not supported with --grpc".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.retry_budget) {
+            return Err(format!("--retry-budget must be between 0 and 1, got {}", self.retry_budget));
+        }
+        if !(self.sample_rate > 0.0 && self.sample_rate <= 1.0) {
+            return Err(format!("--sample-rate must be above 0 and at most 1, got {}", self.sample_rate));
+        }
+        if self.sample_rate < 1.0 && self.interval.is_zero() {
+            return Err("--sample-rate needs a non-zero --interval: a skipped request saves load by waiting out its slot".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!("--jitter must be between 0 and 1, got {}", self.jitter));
+        }
+        if let Some(pem) = &self.ca_cert {
+            // reqwest's own error only says "builder error", so check with native-tls first.
+            self.tls_connector().map_err(|e| format!("Invalid CA certificate: {}", e))?;
+            Certificate::from_pem(pem).map_err(|e| format!("Invalid CA certificate: {}", e))?;
+        }
+        if self.expectations.values().flatten().any(String::is_empty) {
+            return Err("--expect needs a non-empty substring".to_string());
+        }
+        if (self.grpc || self.tcp_only) && !self.expectations.is_empty() {
+            return Err("--expect only applies to HTTP queries, not --grpc or --tcp-only".to_string());
+        }
+        if self.tcp_only {
+            if self.grpc || self.websocket || !self.payloads.is_empty() || self.batch.is_some() {
+                return Err("--tcp-only cannot be combined with --grpc, --websocket, --payload-file or --batch".to_string());
+            }
+            for query in &self.queries {
+                tcp::validate_query(query)?;
+            }
+            for endpoint in &self.endpoints {
+                tcp::target(endpoint)?;
+            }
+        } else if self.grpc {
+            if self.accept_invalid_certs {
+                return Err("--insecure is not supported with --grpc".to_string());
+            }
+            for query in &self.queries {
+                grpc::validate_query(query)?;
+            }
+            for endpoint in &self.endpoints {
+                grpc::channel(endpoint, self.timeout, self.ca_cert.as_deref(), None)?;
+            }
+        } else {
+            for endpoint in &self.endpoints {
+                Url::parse(endpoint).map_err(|e| format!("Invalid endpoint URL {}: {}", endpoint, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A TLS connector with the same certificate settings as the HTTP client,
+    /// for the connection sampler and WebSocket subscriptions.
+    fn tls_connector(&self) -> Result<native_tls::TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder.danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(pem) = &self.ca_cert {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(pem)?);
+        }
+        builder.build()
+    }
+
+    /// Whether `query` is excluded for `endpoint`, ignoring a trailing slash on either URL.
+    fn is_excluded(&self, endpoint: &str, query: &str) -> bool {
+        let endpoint = endpoint.trim_end_matches('/');
+        self.excluded_queries
+            .iter()
+            .any(|(excluded, queries)| excluded.trim_end_matches('/') == endpoint && queries.iter().any(|q| q == query))
+    }
+
+    /// One config per address family to test each endpoint over: two for
+    /// `IpVersion::Both`, otherwise just this one.
+    fn per_family(self: &Arc<Self>) -> Vec<Arc<TestConfig>> {
+        match self.ip_version {
+            IpVersion::Both => [IpVersion::V4, IpVersion::V6]
+                .into_iter()
+                .map(|ip_version| Arc::new(TestConfig { ip_version, ..(**self).clone() }))
+                .collect(),
+            _ => vec![self.clone()],
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Whether a query phase is over: after a stop, once `stop_on_healthy` is
+    /// satisfied, once `max_requests` have been claimed, or otherwise at `end_time`.
+    fn phase_done(&self, end_time: Instant, live: &LiveCounters) -> bool {
+        self.stopped()
+            || self.confirmed_healthy(live)
+            || match self.max_requests {
+                Some(cap) => live.claimed.load(Ordering::Relaxed) >= cap,
+                None => Instant::now() >= end_time,
+            }
+    }
+
+    fn confirmed_healthy(&self, live: &LiveCounters) -> bool {
+        self.stop_on_healthy.is_some_and(|exit| exit.reached(live))
+    }
+
+    /// Sleeps for `duration`, waking early once `stop` is set.
+    async fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        while !self.stopped() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            sleep(left.min(STOP_POLL)).await;
+        }
+    }
+
+    /// `--interval`, randomized by `--jitter`. The mean is unchanged, so jitter
+    /// spreads requests out without changing the average request rate.
+    fn jittered_interval(&self, rng: &mut StdRng) -> Duration {
+        if self.jitter == 0.0 {
+            self.interval
+        } else {
+            self.interval.mul_f64(rng.random_range(1.0 - self.jitter..=1.0 + self.jitter))
+        }
+    }
+
+    /// How long to hold off after `kind`, if `--respect-retry-after` applies to it.
+    fn retry_after(&self, kind: FailureKind) -> Duration {
+        match kind {
+            FailureKind::RateLimited { retry_after: Some(delay) } if self.respect_retry_after => delay,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Prints a warning to stderr, without the icon under `--quiet`.
+    fn eprintln(&self, warning: &str) {
+        if self.quiet {
+            eprintln!("{}", warning);
+        } else {
+            eprintln!("{} {}", "⚠".bright_yellow(), warning);
+        }
+    }
+
+    /// Prints a line without tearing any progress bars currently on screen.
+    fn println(&self, line: &str) {
+        match &self.progress {
+            _ if self.quiet => {}
+            Some(progress) => progress.println(line).unwrap_or_default(),
+            None => println!("{}", line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    Rest,
+    Jsonrpc,
+}
+
+/// HTTP verb for `--protocol rest` requests. JSON-RPC is always POSTed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RequestMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+/// Address family used to reach endpoints. Dual-stack hosts with a broken A or
+/// AAAA record fail only for clients that happen to pick that family.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    /// Whichever family the resolver and happy eyeballs pick.
+    #[default]
+    Auto,
+    V4,
+    V6,
+    /// Test every endpoint twice, over IPv4 and then over IPv6.
+    Both,
+}
+
+impl IpVersion {
+    /// The unspecified local address that restricts connections to this family.
+    fn local_address(self) -> Option<IpAddr> {
+        match self {
+            IpVersion::V4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            IpVersion::V6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+            IpVersion::Auto | IpVersion::Both => None,
+        }
+    }
+
+    fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpVersion::V4 => addr.is_ipv4(),
+            IpVersion::V6 => addr.is_ipv6(),
+            IpVersion::Auto | IpVersion::Both => true,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IpVersion::Auto => "auto",
+            IpVersion::V4 => "v4",
+            IpVersion::V6 => "v6",
+            IpVersion::Both => "both",
+        }
+    }
+
+    /// `IPv4` or `IPv6` for a forced family, for display next to the endpoint.
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            IpVersion::V4 => Some("IPv4"),
+            IpVersion::V6 => Some("IPv6"),
+            IpVersion::Auto | IpVersion::Both => None,
+        }
+    }
+}
+
+// `serde(default)` lets `--baseline` read reports written by older versions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryResult {
+    pub query: String,
+    pub success_count: u64,
+    pub failure_count: u64,
+    pub total_requests: u64,
+    pub failure_rate: f64,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    pub p99_latency_ms: f64,
+    pub avg_latency_ms: f64,
+    pub min_latency_ms: f64,
+    pub max_latency_ms: f64,
+    /// Latency at each `--percentiles` value in ms, keyed like `p50` or `p99.9`.
+    pub percentiles: HashMap<String, f64>,
+    /// Coefficient of variation (stddev / mean) of latency; higher is less steady.
+    pub latency_cv: f64,
+    /// Median absolute deviation of latency in ms; like `latency_cv`, but robust to outliers.
+    pub latency_mad_ms: f64,
+    /// Time to first byte: until response headers arrived, excluding the body download.
+    pub ttfb_p50_ms: f64,
+    pub ttfb_p95_ms: f64,
+    pub ttfb_p99_ms: f64,
+    pub failures_by_kind: HashMap<String, u64>,
+    /// The failure rate split into network path failures (timeouts, refused
+    /// or reset connections) and node software failures (HTTP errors, RPC
+    /// errors, bad responses). They add up to `failure_rate`.
+    pub transport_failure_rate: f64,
+    pub application_failure_rate: f64,
+    pub retried_success_count: u64,
+    /// `retried_success_count` over answered requests: failures that retries hid.
+    pub retry_rate: f64,
+    /// Successful requests slower than `--slow-request-ms`.
+    pub slow_request_count: u64,
+    /// Measured requests skipped by `--sample-rate`; zero without it.
+    pub skipped_count: u64,
+    /// Successful responses that arrived after following redirects.
+    pub redirected_count: u64,
+    /// The longest redirect chain followed and where the latest redirected
+    /// response came from; `None` if the query was never redirected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    /// Share of scheduled measured requests that were sent, with `--sample-rate`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub achieved_sample_rate: Option<f64>,
+    pub invalid_response_count: u64,
+    /// Responses missing an `--expect` substring.
+    pub assertion_failed_count: u64,
+    /// HTTP 429 responses. Counted in `total_requests` but in neither
+    /// `success_count` nor `failure_count`, so they don't affect the score.
+    pub rate_limited_count: u64,
+    pub status_code_breakdown: HashMap<u16, u64>,
+    /// Response body bytes downloaded by successful requests. Failed responses
+    /// are not read, and gRPC probes are not counted.
+    pub bytes_received: u64,
+    /// `bytes_received` per successful request.
+    pub avg_response_bytes: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_histogram: Option<Vec<HistogramBucket>>,
+    /// Per-window counts when `--bucket-secs` is set, oldest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub time_buckets: Vec<WindowStats>,
+    /// The `--slo-p99` target for this query, if one was set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slo_p99_ms: Option<f64>,
+    /// Whether p99 stayed within `slo_p99_ms`. With no successful responses
+    /// there is no p99 to check, which counts as a violation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slo_met: Option<bool>,
+    /// `(satisfied + tolerating / 2) / answered` against `--apdex-target`, from
+    /// 0 (all frustrated) to 1 (all satisfied). Failures count as frustrated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apdex: Option<f64>,
+    /// p99 as a multiple of the fastest endpoint's p99 for the same query in
+    /// this run; `None` unless at least two endpoints answered it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_p99: Option<f64>,
+    /// Fewer than `--min-samples` successful responses, so the percentiles are unreliable.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub low_confidence: bool,
+    /// Every answered request failed with "method not found", so the endpoint
+    /// does not serve this query; it is left out of the endpoint's score.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unsupported: bool,
+    /// When the first failed request was sent, in ms since the run started
+    /// (`run_started_at`); `None` if nothing failed.
+    pub first_failure_offset_ms: Option<u64>,
+    /// When the last successful request was sent, likewise; `None` if nothing succeeded.
+    pub last_success_offset_ms: Option<u64>,
+    /// The slowest successful requests, slowest first (at most `SLOWEST_REQUESTS`).
+    pub slowest_requests: Vec<SlowRequest>,
+    /// The range of workers `--adaptive` let send at once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_effective_concurrency: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_effective_concurrency: Option<usize>,
+}
+
+/// One of a query's slowest requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowRequest {
+    pub latency_ms: f64,
+    /// When the request was sent, in ms since the run started.
+    pub offset_ms: u64,
+}
+
+/// Outcome of the requests started within one `--bucket-secs` window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowStats {
+    pub start_secs: u64,
+    pub success_count: u64,
+    pub failure_count: u64,
+    pub p99_latency_ms: f64,
+}
+
+/// One recorded latency value and how many requests landed on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub value_ms: f64,
+    pub count: u64,
+}
+
+/// Failure rate above which an endpoint counts as down rather than flaky.
+const DOWN_FAILURE_RATE: f64 = 0.95;
+/// Failure rate above which an endpoint that mostly answers counts as flaky.
+const FLAKY_FAILURE_RATE: f64 = 0.01;
+
+/// How many of the slowest requests each query keeps for `slowest_requests`.
+pub const SLOWEST_REQUESTS: usize = 10;
+
+/// What kind of trouble an endpoint is in. A dead node and an intermittently
+/// failing one can both have a high failure rate but call for different fixes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Classification {
+    /// More than 95% of requests failed.
+    Down,
+    /// Failed more than 1% of requests, but not consistently.
+    Flaky,
+    #[default]
+    Healthy,
+}
+
+impl Classification {
+    fn from_failure_rate(failure_rate: f64) -> Self {
+        if failure_rate > DOWN_FAILURE_RATE {
+            Classification::Down
+        } else if failure_rate > FLAKY_FAILURE_RATE {
+            Classification::Flaky
+        } else {
+            Classification::Healthy
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Classification::Down => "down",
+            Classification::Flaky => "flaky",
+            Classification::Healthy => "healthy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointReport {
+    pub endpoint: String,
+    /// The endpoint's region tag (`url#region`), if it had one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub overall_success_rate: f64,
+    pub overall_failure_rate: f64,
+    pub flakiness_score: f64,
+    /// Exponential moving average of `flakiness_score` over `--watch` cycles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothed_score: Option<f64>,
+    pub classification: Classification,
+    /// The address family the endpoint was tested over.
+    pub ip_version: IpVersion,
+    /// Whether every request used a new connection (`--no-keepalive`).
+    pub fresh_connections: bool,
+    /// A query phase ended early because `--stop-on-healthy` was satisfied.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stopped_early: bool,
+    /// Queries skipped for this endpoint with `--exclude`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub excluded_queries: Vec<String>,
+    /// Queries flagged `unsupported`, which the score leaves out.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_queries: Vec<String>,
+    /// `retry_rate` over all scored queries.
+    pub retry_rate: f64,
+    /// `retry_rate` exceeded `--retry-budget`, and the excess was scored as failures.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub retry_budget_exceeded: bool,
+    pub total_requests: u64,
+    pub test_duration_secs: u64,
+    pub latest_block_height: Option<u64>,
+    /// Block and app hash from the same `status` response as `latest_block_height`.
+    pub latest_block_hash: Option<String>,
+    pub latest_app_hash: Option<String>,
+    pub height_lag: Option<u64>,
+    /// Median fresh-connection setup times; `tls_p50_ms` is `None` for plain HTTP.
+    pub dns_p50_ms: Option<f64>,
+    pub connect_p50_ms: Option<f64>,
+    pub tls_p50_ms: Option<f64>,
+    /// Share of HTTP requests sent over a pooled keep-alive connection instead
+    /// of a new one; `None` for gRPC and TCP. Low reuse with high latency
+    /// points to a server or proxy closing connections early.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_reuse_rate: Option<f64>,
+    /// `bytes_received` summed over all queries.
+    pub total_bytes_received: u64,
+    /// Mean correlation between queries' per-second failure rates: near 1 when
+    /// failures hit every query at once (a node-wide problem), near 0 when they
+    /// are independent (query-specific). Only set when queries overlapped
+    /// in time, i.e. with `--interleave` or `--parallel-queries`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_correlation: Option<f64>,
+    pub queries: Vec<QueryResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub websocket: Option<WebSocketReport>,
+}
+
+/// A semaphore every worker holds a permit of while its request (with any
+/// retries) is in flight. Clones share the permits and the counters.
+#[derive(Debug, Clone)]
+pub struct GlobalLimit {
+    max: usize,
+    permits: Arc<Semaphore>,
+    in_flight: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+    waited: Arc<AtomicU64>,
+}
+
+/// How close a run came to its `GlobalLimit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlobalConcurrency {
+    pub limit: usize,
+    /// Most requests in flight at once across all endpoints.
+    pub peak_in_flight: usize,
+    /// Requests that had to wait for a permit; non-zero means the cap was hit.
+    pub requests_waited: u64,
+}
+
+/// Holds one `GlobalLimit` permit and counts the request as in flight until dropped.
+struct GlobalPermit<'a> {
+    _permit: SemaphorePermit<'a>,
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for GlobalPermit<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl GlobalLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+            in_flight: Arc::default(),
+            peak: Arc::default(),
+            waited: Arc::default(),
+        }
+    }
+
+    async fn acquire(&self) -> GlobalPermit<'_> {
+        let permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.waited.fetch_add(1, Ordering::Relaxed);
+                self.permits.acquire().await.expect("the semaphore is never closed")
+            }
+        };
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(in_flight, Ordering::Relaxed);
+        GlobalPermit { _permit: permit, in_flight: &self.in_flight }
+    }
+
+    fn stats(&self) -> GlobalConcurrency {
+        GlobalConcurrency {
+            limit: self.max,
+            peak_in_flight: self.peak.load(Ordering::Relaxed),
+            requests_waited: self.waited.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// An append-only NDJSON file with one line per failed request, to line up
+/// with node logs. Writing stops once the file would grow past `max_bytes`.
+/// Clones share the file.
+#[derive(Debug, Clone)]
+pub struct FailureLog {
+    file: Arc<Mutex<File>>,
+    max_bytes: Option<u64>,
+    /// The file's size, including whatever it held before the run.
+    size: Arc<AtomicU64>,
+    full: Arc<AtomicBool>,
+}
+
+/// One line of a `FailureLog`.
+#[derive(Serialize)]
+struct FailureRecord<'a> {
+    timestamp: String,
+    endpoint: &'a str,
+    query: &'a str,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<&'a str>,
+}
+
+impl FailureLog {
+    pub fn open(path: &str, max_bytes: Option<u64>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            max_bytes,
+            size: Arc::new(AtomicU64::new(size)),
+            full: Arc::default(),
+        })
+    }
+
+    /// Appends `failure` unless the file is full. Each line is written in one
+    /// go, so a run killed mid-way leaves no partial line behind.
+    fn record(&self, config: &TestConfig, endpoint: &str, query: &str, failure: &QueryFailure, latency: Duration) {
+        if self.full.load(Ordering::Relaxed) {
+            return;
+        }
+        let record = FailureRecord {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            endpoint,
+            query,
+            kind: failure.kind.as_str(),
+            status: failure.status,
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            snippet: failure.snippet.as_deref(),
+        };
+        let line = serde_json::to_string(&record).unwrap() + "\n";
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let size = self.size.load(Ordering::Relaxed) + line.len() as u64;
+        if self.max_bytes.is_some_and(|max| size > max) {
+            if !self.full.swap(true, Ordering::Relaxed) {
+                config.eprintln("Failure log reached its size limit; later failures are not recorded");
+            }
+            return;
+        }
+        match file.write_all(line.as_bytes()) {
+            Ok(()) => self.size.store(size, Ordering::Relaxed),
+            Err(e) => {
+                self.full.store(true, Ordering::Relaxed);
+                config.eprintln(&format!("Failed to write the failure log, no longer recording: {}", e));
+            }
+        }
+    }
+}
+
+/// Shortest gap between two slow request lines, so a stalling node cannot
+/// flood the console.
+const SLOW_LOG_EVERY: Duration = Duration::from_secs(1);
+
+/// Prints successful requests slower than `threshold` while the run is going.
+/// At most one line goes out per `SLOW_LOG_EVERY` across every endpoint; the
+/// next line says how many were held back. Clones share the rate limit.
+#[derive(Debug, Clone)]
+pub struct SlowRequestLog {
+    threshold: Duration,
+    last_printed: Arc<Mutex<Option<Instant>>>,
+    suppressed: Arc<AtomicU64>,
+}
+
+impl SlowRequestLog {
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold, last_printed: Arc::default(), suppressed: Arc::default() }
+    }
+
+    /// Whether `latency` counts as slow, logging it if the rate limit allows.
+    fn check(&self, config: &TestConfig, endpoint: &str, query: &str, latency: Duration) -> bool {
+        if latency <= self.threshold {
+            return false;
+        }
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        warn!(query = %query, latency_ms, threshold_ms = self.threshold.as_millis() as u64, "slow request");
+        let now = Instant::now();
+        {
+            let mut last_printed = self.last_printed.lock().unwrap_or_else(|e| e.into_inner());
+            if last_printed.is_some_and(|at| now.duration_since(at) < SLOW_LOG_EVERY) {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            *last_printed = Some(now);
+        }
+        let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+        let held_back = if suppressed > 0 { format!(" (+{} more held back)", suppressed) } else { String::new() };
+        config.println(&format!("  {} [{}] Slow request: {} @ {} took {:.1}ms{}",
+                                "🐌".bright_yellow(),
+                                Utc::now().format("%H:%M:%S%.3f"),
+                                query.bright_white(),
+                                endpoint.bright_cyan(),
+                                latency_ms,
+                                held_back));
+        true
+    }
+}
+
+/// Per-query counters shared by all workers. Atomics keep the hot path lock-free
+/// and can be read while the test is still running.
+#[derive(Debug, Default)]
+struct LiveCounters {
+    success: AtomicU64,
+    failure: AtomicU64,
+    /// Measured requests started so far, counted against `max_requests`.
+    claimed: AtomicU64,
+    /// Requests started per query of the phase, warmup included, which picks
+    /// the next value of any `expand_query` placeholders.
+    sent: Vec<AtomicU64>,
+    /// Workers allowed to send under `--adaptive`; the others are parked.
+    limit: AtomicUsize,
+}
+
+/// How often `--adaptive` re-evaluates the failure rate and adjusts the limit.
+const ADAPTIVE_WINDOW: Duration = Duration::from_secs(1);
+/// How long a worker parked by `--adaptive` waits before checking the limit again.
+const ADAPTIVE_PARK: Duration = Duration::from_millis(100);
+
+/// AIMD control of a phase's effective concurrency (`LiveCounters::limit`):
+/// halved when a window's failure rate exceeds the threshold, otherwise raised
+/// by one up to the phase's worker count.
+struct AdaptiveConcurrency {
+    threshold: f64,
+    workers: usize,
+    window_start: Instant,
+    /// `success` and `failure` at the start of the window.
+    counted: (u64, u64),
+    lowest: usize,
+    highest: usize,
+}
+
+impl AdaptiveConcurrency {
+    fn new(threshold: f64, workers: usize) -> Self {
+        Self {
+            threshold,
+            workers,
+            window_start: Instant::now(),
+            counted: (0, 0),
+            lowest: workers,
+            highest: workers,
+        }
+    }
+
+    fn adjust(&mut self, live: &LiveCounters) {
+        if self.window_start.elapsed() < ADAPTIVE_WINDOW {
+            return;
+        }
+        let counted = (live.success.load(Ordering::Relaxed), live.failure.load(Ordering::Relaxed));
+        let (success, failure) = (counted.0 - self.counted.0, counted.1 - self.counted.1);
+        self.window_start = Instant::now();
+        self.counted = counted;
+        if success + failure == 0 {
+            return;
+        }
+        let limit = live.limit.load(Ordering::Relaxed);
+        let limit = if failure as f64 / (success + failure) as f64 > self.threshold {
+            (limit / 2).max(1)
+        } else {
+            (limit + 1).min(self.workers)
+        };
+        live.limit.store(limit, Ordering::Relaxed);
+        self.lowest = self.lowest.min(limit);
+        self.highest = self.highest.max(limit);
+    }
+}
+
+const PROGRESS_TICK: Duration = Duration::from_millis(250);
+/// How often long waits check `TestConfig::stop`.
+const STOP_POLL: Duration = Duration::from_millis(250);
+const PLAIN_PROGRESS_EVERY: Duration = Duration::from_secs(10);
+
+/// Live progress for one query, read off its `LiveCounters`: a bar on a terminal,
+/// otherwise a plain line every `PLAIN_PROGRESS_EVERY` so piped logs stay readable.
+struct QueryProgress {
+    label: String,
+    started: Instant,
+    total: Duration,
+    /// Counts requests against this cap instead of time against `total`.
+    requests: Option<u64>,
+    live: Arc<LiveCounters>,
+    bar: Option<ProgressBar>,
+    quiet: bool,
+    last_line: Instant,
+}
+
+impl QueryProgress {
+    fn start(
+        config: &TestConfig,
+        label: String,
+        total: Duration,
+        requests: Option<u64>,
+        live: Arc<LiveCounters>,
+    ) -> Self {
+        let length = requests.unwrap_or(total.as_millis() as u64);
+        let bar = config.progress.as_ref().filter(|_| !config.quiet).map(|progress| {
+            // Follow `colored`, which the caller may have switched off.
+            let template = if colored::control::SHOULD_COLORIZE.should_colorize() {
+                "    {prefix} [{elapsed_precise}] {bar:30.cyan/blue} eta {eta} {msg}"
+            } else {
+                "    {prefix} [{elapsed_precise}] {bar:30} eta {eta} {msg}"
+            };
+            let style = ProgressStyle::with_template(template).unwrap();
+            progress.add(
+                ProgressBar::new(length)
+                    .with_style(style)
+                    .with_prefix(label.clone()),
+            )
+        });
+        let now = Instant::now();
+        Self { label, started: now, total, requests, live, bar, quiet: config.quiet, last_line: now }
+    }
+
+    fn tick(&mut self) {
+        let elapsed = self.started.elapsed().min(self.total);
+        let success = self.live.success.load(Ordering::Relaxed);
+        let failure = self.live.failure.load(Ordering::Relaxed);
+        let claimed = self.requests.map(|cap| self.live.claimed.load(Ordering::Relaxed).min(cap));
+        match &self.bar {
+            Some(bar) => {
+                bar.set_position(claimed.unwrap_or(elapsed.as_millis() as u64));
+                bar.set_message(format!("✓ {} ✗ {}", success, failure));
+            }
+            None if !self.quiet && self.last_line.elapsed() >= PLAIN_PROGRESS_EVERY => {
+                self.last_line = Instant::now();
+                let done = match (claimed, self.requests) {
+                    (Some(claimed), Some(cap)) => format!("{}/{} requests", claimed, cap),
+                    _ => format!("{}s/{}s", elapsed.as_secs(), self.total.as_secs()),
+                };
+                println!("    … {}: {} ✓ {} ✗ {}", self.label, done, success, failure);
+            }
+            None => {}
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Everything a single worker records. Each worker owns its metrics outright and
+/// they are merged once the workers finish, so recording never contends.
+#[derive(Debug)]
+struct TestMetrics {
+    success_count: u64,
+    retried_success_count: u64,
+    slow_request_count: u64,
+    /// Requests `--sample-rate` left out, counted like measured requests.
+    skipped_count: u64,
+    failure_count: u64,
+    rate_limited_count: u64,
+    failure_kinds: HashMap<String, u64>,
+    /// HTTP status of every response that arrived, successful or not.
+    status_codes: HashMap<u16, u64>,
+    latencies: Histogram<u64>,
+    ttfb_latencies: Histogram<u64>,
+    last_response: Option<Value>,
+    /// Response body bytes of successful requests.
+    bytes_received: u64,
+    /// Successful responses that followed redirects, the longest chain among
+    /// them, and where the latest one ended up.
+    redirected_count: u64,
+    redirect_hops: usize,
+    final_url: Option<String>,
+    /// When the first failed and the last successful request were sent.
+    first_failure_at: Option<Instant>,
+    last_success_at: Option<Instant>,
+    /// The `SLOWEST_REQUESTS` highest latencies and when those requests were
+    /// sent, as a min-heap so the fastest of them is evicted first.
+    slowest: BinaryHeap<Reverse<(Duration, Instant)>>,
+    /// Lowest and highest `--adaptive` limit of the phase, set once it ends.
+    effective_concurrency: Option<(usize, usize)>,
+    windows: Vec<WindowMetrics>,
+    timeline: FailureTimeline,
+    phases: ConnectionPhases,
+}
+
+/// Width of the windows in which failures of different queries count as co-occurring.
+const CORRELATION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Windows two queries must both have sent requests in before their failure rates are compared.
+const MIN_CORRELATION_WINDOWS: usize = 5;
+
+/// Measured requests and failures per `CORRELATION_WINDOW`, keyed by window
+/// number since the run started, so windows line up across queries and phases.
+#[derive(Debug, Default)]
+struct FailureTimeline {
+    windows: BTreeMap<u64, (u64, u64)>,
+}
+
+impl FailureTimeline {
+    fn record(&mut self, run_start: Instant, started: Instant, failed: bool) {
+        let window = started.saturating_duration_since(run_start).as_secs() / CORRELATION_WINDOW.as_secs();
+        let (sent, failures) = self.windows.entry(window).or_insert((0, 0));
+        *sent += 1;
+        *failures += failed as u64;
+    }
+
+    fn merge(&mut self, other: FailureTimeline) {
+        for (window, (sent, failures)) in other.windows {
+            let (mine, my_failures) = self.windows.entry(window).or_insert((0, 0));
+            *mine += sent;
+            *my_failures += failures;
+        }
+    }
+
+    /// Pearson correlation of the two queries' failure rates over the windows
+    /// both sent requests in. `None` with too little overlap, or if either rate
+    /// never changed, where there is nothing to correlate.
+    fn correlation(&self, other: &FailureTimeline) -> Option<f64> {
+        let rate = |(sent, failures): &(u64, u64)| *failures as f64 / *sent as f64;
+        let pairs: Vec<(f64, f64)> = self
+            .windows
+            .iter()
+            .filter_map(|(window, mine)| other.windows.get(window).map(|theirs| (rate(mine), rate(theirs))))
+            .collect();
+        if pairs.len() < MIN_CORRELATION_WINDOWS {
+            return None;
+        }
+        let n = pairs.len() as f64;
+        let (mean_a, mean_b) = (
+            pairs.iter().map(|(a, _)| a).sum::<f64>() / n,
+            pairs.iter().map(|(_, b)| b).sum::<f64>() / n,
+        );
+        let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+        for (a, b) in &pairs {
+            covariance += (a - mean_a) * (b - mean_b);
+            variance_a += (a - mean_a).powi(2);
+            variance_b += (b - mean_b).powi(2);
+        }
+        (variance_a > 0.0 && variance_b > 0.0).then(|| covariance / (variance_a * variance_b).sqrt())
+    }
+}
+
+/// Mean `FailureTimeline::correlation` over every pair of queries it is defined for.
+fn failure_correlation(timelines: &[FailureTimeline]) -> Option<f64> {
+    let correlations: Vec<f64> = timelines
+        .iter()
+        .enumerate()
+        .flat_map(|(index, a)| timelines[index + 1..].iter().filter_map(|b| a.correlation(b)))
+        .collect();
+    (!correlations.is_empty()).then(|| correlations.iter().sum::<f64>() / correlations.len() as f64)
+}
+
+/// Fresh-connection setup times in microseconds, recorded by `sample_connection_phases`.
+#[derive(Debug)]
+struct ConnectionPhases {
+    dns: Histogram<u64>,
+    connect: Histogram<u64>,
+    tls: Histogram<u64>,
+}
+
+impl ConnectionPhases {
+    fn new() -> Self {
+        Self {
+            dns: Histogram::<u64>::new(3).unwrap(),
+            connect: Histogram::<u64>::new(3).unwrap(),
+            tls: Histogram::<u64>::new(3).unwrap(),
+        }
+    }
+
+    fn merge(&mut self, other: &ConnectionPhases) {
+        for (mine, theirs) in [
+            (&mut self.dns, &other.dns),
+            (&mut self.connect, &other.connect),
+            (&mut self.tls, &other.tls),
+        ] {
+            mine.add(theirs).expect("phase histograms share the same configuration");
+        }
+    }
+
+    /// Median in milliseconds, or `None` if the phase was never timed.
+    fn median_ms(histogram: &Histogram<u64>) -> Option<f64> {
+        (!histogram.is_empty()).then(|| histogram.value_at_quantile(0.5) as f64 / 1000.0)
+    }
+}
+
+/// Raw counts for one time window, indexed by window number in `TestMetrics::windows`.
+#[derive(Debug)]
+struct WindowMetrics {
+    success_count: u64,
+    failure_count: u64,
+    latencies: Histogram<u64>,
+}
+
+impl WindowMetrics {
+    fn new() -> Self {
+        Self {
+            success_count: 0,
+            failure_count: 0,
+            latencies: Histogram::<u64>::new(3).unwrap(),
+        }
+    }
+}
+
+impl TestMetrics {
+    fn new() -> Self {
+        Self {
+            success_count: 0,
+            retried_success_count: 0,
+            slow_request_count: 0,
+            skipped_count: 0,
+            failure_count: 0,
+            rate_limited_count: 0,
+            failure_kinds: HashMap::new(),
+            status_codes: HashMap::new(),
+            latencies: Histogram::<u64>::new(3).unwrap(),
+            ttfb_latencies: Histogram::<u64>::new(3).unwrap(),
+            last_response: None,
+            bytes_received: 0,
+            redirected_count: 0,
+            redirect_hops: 0,
+            final_url: None,
+            first_failure_at: None,
+            last_success_at: None,
+            slowest: BinaryHeap::with_capacity(SLOWEST_REQUESTS + 1),
+            effective_concurrency: None,
+            windows: Vec::new(),
+            timeline: FailureTimeline::default(),
+            phases: ConnectionPhases::new(),
+        }
+    }
+
+    fn window_mut(&mut self, index: usize) -> &mut WindowMetrics {
+        if self.windows.len() <= index {
+            self.windows.resize_with(index + 1, WindowMetrics::new);
+        }
+        &mut self.windows[index]
+    }
+
+    /// Counts a request in its time window; `latency` is `None` for a failure.
+    fn record_window(&mut self, index: usize, latency: Option<Duration>) {
+        let window = self.window_mut(index);
+        match latency {
+            Some(latency) => {
+                window.success_count += 1;
+                let _ = window.latencies.record(latency.as_micros() as u64);
+            }
+            None => window.failure_count += 1,
+        }
+    }
+
+    fn record_status(&mut self, status: Option<u16>) {
+        if let Some(status) = status {
+            *self.status_codes.entry(status).or_insert(0) += 1;
+        }
+    }
+
+    fn record_success(&mut self, response: QueryResponse) {
+        self.record_status(response.status);
+        self.success_count += 1;
+        if response.retried {
+            self.retried_success_count += 1;
+        }
+        let _ = self.latencies.record(response.latency.as_micros() as u64);
+        let _ = self.ttfb_latencies.record(response.ttfb.as_micros() as u64);
+        self.bytes_received += response.bytes;
+        if let Some((hops, url)) = response.redirect {
+            self.redirected_count += 1;
+            self.redirect_hops = self.redirect_hops.max(hops);
+            self.final_url = Some(url);
+        }
+        if response.body.is_some() {
+            self.last_response = response.body;
+        }
+    }
+
+    fn record_slowest(&mut self, latency: Duration, started: Instant) {
+        self.slowest.push(Reverse((latency, started)));
+        if self.slowest.len() > SLOWEST_REQUESTS {
+            self.slowest.pop();
+        }
+    }
+
+    fn record_failure(&mut self, failure: QueryFailure) {
+        self.record_status(failure.status);
+        self.failure_count += 1;
+        *self.failure_kinds.entry(failure.kind.as_str().to_string()).or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: TestMetrics) {
+        self.success_count += other.success_count;
+        self.retried_success_count += other.retried_success_count;
+        self.slow_request_count += other.slow_request_count;
+        self.skipped_count += other.skipped_count;
+        self.failure_count += other.failure_count;
+        self.rate_limited_count += other.rate_limited_count;
+        for (kind, count) in other.failure_kinds {
+            *self.failure_kinds.entry(kind).or_insert(0) += count;
+        }
+        for (status, count) in other.status_codes {
+            *self.status_codes.entry(status).or_insert(0) += count;
+        }
+        self.latencies
+            .add(&other.latencies)
+            .expect("worker histograms share the same configuration");
+        self.ttfb_latencies
+            .add(&other.ttfb_latencies)
+            .expect("worker histograms share the same configuration");
+        if other.last_response.is_some() {
+            self.last_response = other.last_response;
+        }
+        self.bytes_received += other.bytes_received;
+        self.redirected_count += other.redirected_count;
+        self.redirect_hops = self.redirect_hops.max(other.redirect_hops);
+        if other.final_url.is_some() {
+            self.final_url = other.final_url;
+        }
+        self.first_failure_at = self.first_failure_at.into_iter().chain(other.first_failure_at).min();
+        self.last_success_at = self.last_success_at.max(other.last_success_at);
+        for Reverse((latency, started)) in other.slowest {
+            self.record_slowest(latency, started);
+        }
+        self.phases.merge(&other.phases);
+        self.timeline.merge(other.timeline);
+        for (index, theirs) in other.windows.into_iter().enumerate() {
+            let window = self.window_mut(index);
+            window.success_count += theirs.success_count;
+            window.failure_count += theirs.failure_count;
+            window
+                .latencies
+                .add(&theirs.latencies)
+                .expect("worker histograms share the same configuration");
+        }
+    }
+}
+
+/// Coarse failure buckets, so a timing-out node can be told apart from one
+/// that is rate-limiting (4xx) or crashing (5xx).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    Timeout,
+    Connect,
+    Http4xx,
+    Http5xx,
+    HttpOther,
+    Decode,
+    RpcError,
+    /// JSON-RPC error -32601: the node does not serve this method at all.
+    MethodNotFound,
+    InvalidResponse,
+    /// The body lacked an `--expect` substring.
+    AssertionFailed,
+    /// HTTP 429, with the `Retry-After` delay if the server sent one.
+    RateLimited { retry_after: Option<Duration> },
+    /// A 3xx response with `--follow-redirects false`.
+    Redirect,
+    /// A redirect chain longer than `--max-redirects`.
+    TooManyRedirects,
+    /// The request could not be built, e.g. the query does not form a valid
+    /// URL. Never sent, so it says nothing about the network path.
+    InvalidRequest,
+    Other,
+}
+
+impl FailureKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            FailureKind::Timeout => "timeout",
+            FailureKind::Connect => "connect",
+            FailureKind::Http4xx => "http_4xx",
+            FailureKind::Http5xx => "http_5xx",
+            FailureKind::HttpOther => "http_other",
+            FailureKind::Decode => "decode",
+            FailureKind::RpcError => "rpc_error",
+            FailureKind::MethodNotFound => "method_not_found",
+            FailureKind::InvalidResponse => "invalid_response",
+            FailureKind::AssertionFailed => "assertion_failed",
+            FailureKind::RateLimited { .. } => "rate_limited",
+            FailureKind::Redirect => "redirect",
+            FailureKind::TooManyRedirects => "too_many_redirects",
+            FailureKind::InvalidRequest => "invalid_request",
+            FailureKind::Other => "other",
+        }
+    }
+
+    /// Failures of the network path rather than the node software: no
+    /// connection, no answer in time, or a request cut off (`Other` is what
+    /// reqwest reports for a reset connection; requests that could not even be
+    /// built are `InvalidRequest`). Everything else is an application failure.
+    const TRANSPORT: [FailureKind; 3] = [FailureKind::Timeout, FailureKind::Connect, FailureKind::Other];
+
+    /// How many of `failures_by_kind` are `TRANSPORT` failures.
+    fn transport_count(failures_by_kind: &HashMap<String, u64>) -> u64 {
+        FailureKind::TRANSPORT.iter().filter_map(|kind| failures_by_kind.get(kind.as_str())).sum()
+    }
+
+    /// `RpcError`, or `MethodNotFound` for JSON-RPC error code -32601.
+    fn from_rpc_error(error: &Value) -> Self {
+        if error.get("code").and_then(Value::as_i64) == Some(-32601) {
+            FailureKind::MethodNotFound
+        } else {
+            FailureKind::RpcError
+        }
+    }
+
+    fn from_status(status: reqwest::StatusCode) -> Self {
+        if status.is_client_error() {
+            FailureKind::Http4xx
+        } else if status.is_server_error() {
+            FailureKind::Http5xx
+        } else if status.is_redirection() {
+            FailureKind::Redirect
+        } else {
+            FailureKind::HttpOther
+        }
+    }
+
+    /// Only the delay-seconds form of `Retry-After` is understood; an HTTP date is ignored.
+    fn rate_limited(headers: &HeaderMap) -> Self {
+        let retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        FailureKind::RateLimited { retry_after }
+    }
+
+    fn from_reqwest(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            FailureKind::Timeout
+        } else if error.is_connect() {
+            FailureKind::Connect
+        } else if error.is_decode() || error.is_body() {
+            FailureKind::Decode
+        } else if error.is_redirect() {
+            FailureKind::TooManyRedirects
+        } else if error.is_builder() {
+            FailureKind::InvalidRequest
+        } else {
+            FailureKind::Other
+        }
+    }
+}
+
+/// A failed probe, with the HTTP status if a response arrived at all (the body
+/// of a 200 can still fail validation).
+#[derive(Debug, Clone)]
+struct QueryFailure {
+    kind: FailureKind,
+    status: Option<u16>,
+    /// The start of the response body, for `TestConfig::failure_log`.
+    snippet: Option<String>,
+}
+
+impl From<FailureKind> for QueryFailure {
+    fn from(kind: FailureKind) -> Self {
+        Self { kind, status: None, snippet: None }
+    }
+}
+
+/// Bytes of a failed response's body kept as its `snippet`.
+const FAILURE_SNIPPET_BYTES: usize = 200;
+
+fn snippet(body: &[u8]) -> String {
+    String::from_utf8_lossy(&body[..body.len().min(FAILURE_SNIPPET_BYTES)]).into_owned()
+}
+
+/// A successful probe. `latency` runs until the whole body was read, `ttfb` only
+/// until the headers arrived. `body` is only present when the response had to be
+/// parsed (JSON-RPC mode or a validated query).
+#[derive(Debug)]
+struct QueryResponse {
+    latency: Duration,
+    ttfb: Duration,
+    /// `None` for gRPC probes.
+    status: Option<u16>,
+    body: Option<Value>,
+    /// Size of the response body as read; 0 for gRPC probes.
+    bytes: u64,
+    retried: bool,
+    /// Redirect hops followed and the URL the response finally came from.
+    redirect: Option<(usize, String)>,
+}
+
+/// Checks that a successful (HTTP 200) response actually carries the expected payload.
+pub type ResponseValidator = fn(&Value) -> bool;
+
+/// Built-in validators keyed by RPC method name. Nodes sometimes answer 200 with an
+/// empty or error body; these catch the most common "lying" responses.
+pub fn response_validators() -> HashMap<String, ResponseValidator> {
+    let mut validators: HashMap<String, ResponseValidator> = HashMap::new();
+    validators.insert("status".to_string(), |body| {
+        body.pointer("/result/sync_info/latest_block_height").is_some()
+    });
+    validators.insert("abci_info".to_string(), |body| body.pointer("/result/response").is_some());
+    validators
+}
+
+/// The RPC method of a REST-style query, i.e. everything before the `?`.
+fn query_method(query: &str) -> &str {
+    query.split_once('?').map_or(query, |(method, _)| method)
+}
+
+/// Fills each `{FIRST..LAST}` (inclusive integer range) or `{a|b|c}` placeholder
+/// in `query` with its `n`th value, wrapping around, so that successive requests
+/// rotate through the values instead of being answered from a server-side cache.
+/// All placeholders advance together.
+fn expand_query(query: &str, n: u64) -> Result<Cow<'_, str>, String> {
+    if !query.contains('{') {
+        return Ok(Cow::Borrowed(query));
+    }
+    let mut expanded = String::new();
+    let mut rest = query;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..]
+            .find('}')
+            .map(|offset| open + offset)
+            .ok_or_else(|| format!("Unclosed {{ in query \"{}\"", query))?;
+        expanded.push_str(&rest[..open]);
+        let spec = &rest[open + 1..close];
+        if let Some((first, last)) = spec.split_once("..") {
+            let bound = |value: &str| {
+                value.trim().parse::<u64>().map_err(|_| format!("Invalid range {{{}}} in query \"{}\"", spec, query))
+            };
+            let (first, last) = (bound(first)?, bound(last)?);
+            if first > last {
+                return Err(format!("Empty range {{{}}} in query \"{}\"", spec, query));
+            }
+            expanded.push_str(&(first + n % (last - first).saturating_add(1)).to_string());
+        } else if spec.is_empty() {
+            return Err(format!("Empty {{}} in query \"{}\": use {{FIRST..LAST}} or {{a|b|c}}", query));
+        } else {
+            let values: Vec<&str> = spec.split('|').collect();
+            expanded.push_str(values[(n % values.len() as u64) as usize]);
+        }
+        rest = &rest[close + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+/// Looks up a per-query setting by the exact query string, then by its method name.
+fn query_override<'a, T>(overrides: &'a HashMap<String, T>, query: &str) -> Option<&'a T> {
+    overrides.get(query).or_else(|| overrides.get(query_method(query)))
+}
+
+/// Builds a JSON-RPC 2.0 request from a REST-style query such as `block?height=1`:
+/// the path becomes the method and the query-string pairs become named params.
+fn jsonrpc_request_body(query: &str) -> Value {
+    let method = query_method(query);
+    let raw_params = query.split_once('?').map_or("", |(_, params)| params);
+    let params: Map<String, Value> = raw_params
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), Value::String(value.trim_matches('"').to_string()))
+        })
+        .collect();
+
+    json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+}
+
+/// The REST URL for `query` under `endpoint`, resolved with `Url::join` against
+/// the endpoint as a directory: `https://host/rpc` and `https://host/rpc/` both
+/// give `https://host/rpc/status`. Query parameters on the endpoint (such as an
+/// API key) are carried over.
+fn query_url(endpoint: &str, query: &str) -> Option<Url> {
+    let mut base = Url::parse(endpoint).ok()?;
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    let mut url = base.join(query).ok()?;
+    if base.query().is_some() {
+        url.query_pairs_mut().extend_pairs(base.query_pairs());
+    }
+    Some(url)
+}
+
+/// `n` copies of the `jsonrpc_request_body` call in one batch array, with ids 1 to `n`.
+fn jsonrpc_batch_body(query: &str, n: usize) -> Value {
+    let call = jsonrpc_request_body(query);
+    Value::Array(
+        (1..=n)
+            .map(|id| {
+                let mut call = call.clone();
+                call["id"] = json!(id);
+                call
+            })
+            .collect(),
+    )
+}
+
+async fn test_endpoint_query(
+    client: &Client,
+    endpoint: &str,
+    query: &str,
+    config: &TestConfig,
+) -> Result<QueryResponse, QueryFailure> {
+    let protocol = config.protocol;
+    let validator = config.validators.get(query_method(query)).copied();
+    let timeout = query_override(&config.query_timeouts, query).copied();
+    let payload = config.payloads.get(query);
+    // A custom payload is always a single JSON-RPC call, whatever `--protocol` says.
+    let jsonrpc = protocol == Protocol::Jsonrpc || payload.is_some();
+    let batch = config.batch.filter(|_| payload.is_none());
+    let mut request = match (payload, protocol) {
+        (Some(body), _) => client.post(endpoint).json(body),
+        (None, Protocol::Rest) => {
+            let url = query_url(endpoint, query)
+                .ok_or(QueryFailure::from(FailureKind::InvalidRequest))?;
+            match config.method {
+                RequestMethod::Get => client.get(url),
+                RequestMethod::Post => client.post(url),
+            }
+        }
+        (None, Protocol::Jsonrpc) => match batch {
+            Some(n) => client.post(endpoint).json(&jsonrpc_batch_body(query, n)),
+            None => client.post(endpoint).json(&jsonrpc_request_body(query)),
+        },
+    };
+    if !config.query_params.is_empty() {
+        request = request.query(&config.query_params);
+    }
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let start = Instant::now();
+    let (sent, hops) = REDIRECT_HOPS
+        .scope(Cell::new(0), async { (request.send().await, REDIRECT_HOPS.with(Cell::get)) })
+        .await;
+    
+    match sent {
+        Ok(response) => {
+            let ttfb = start.elapsed();
+            let status = response.status();
+            let redirect = (hops > 0).then(|| (hops, response.url().to_string()));
+            let failed = |kind| QueryFailure { kind, status: Some(status.as_u16()), snippet: None };
+            // Only worth keeping when there is a failure log to write it to.
+            let rejected = |kind, body: &[u8]| QueryFailure {
+                snippet: config.failure_log.is_some().then(|| snippet(body)),
+                ..failed(kind)
+            };
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                return Err(failed(FailureKind::rate_limited(response.headers())));
+            }
+            if status == reqwest::StatusCode::NOT_FOUND {
+                // Tendermint answers an unknown REST route with a JSON-RPC error body.
+                let body = response.bytes().await.unwrap_or_default();
+                let error = serde_json::from_slice::<Value>(&body).ok().and_then(|body| body.get("error").cloned());
+                return Err(rejected(error.map_or(FailureKind::Http4xx, |e| FailureKind::from_rpc_error(&e)), &body));
+            }
+            if !status.is_success() {
+                let kind = FailureKind::from_status(status);
+                if config.failure_log.is_none() {
+                    return Err(failed(kind));
+                }
+                let body = response.bytes().await.unwrap_or_default();
+                return Err(rejected(kind, &body));
+            }
+            let bytes = response.bytes().await.map_err(|e| failed(FailureKind::from_reqwest(&e)))?;
+            let elapsed = start.elapsed();
+            // Checked before parsing, so an HTML error page served with a 200 fails here.
+            if let Some(expected) = query_override(&config.expectations, query)
+                && !expected.iter().all(|needle| contains(&bytes, needle.as_bytes()))
+            {
+                return Err(rejected(FailureKind::AssertionFailed, &bytes));
+            }
+            let mut parsed = None;
+            if jsonrpc || validator.is_some() {
+                let body: Value = serde_json::from_slice(&bytes).map_err(|_| rejected(FailureKind::Decode, &bytes))?;
+                // A batch must answer every call; each answer is checked like a single response.
+                let calls = match (batch, &body) {
+                    (None, body) => std::slice::from_ref(body),
+                    (Some(n), Value::Array(calls)) if calls.len() == n => calls.as_slice(),
+                    (Some(_), _) => return Err(rejected(FailureKind::InvalidResponse, &bytes)),
+                };
+                for call in calls {
+                    if jsonrpc {
+                        if let Some(error) = call.get("error").filter(|e| !e.is_null()) {
+                            return Err(rejected(FailureKind::from_rpc_error(error), &bytes));
+                        }
+                        if call.get("result").is_none() {
+                            return Err(rejected(FailureKind::Decode, &bytes));
+                        }
+                    }
+                    if validator.is_some_and(|is_valid| !is_valid(call)) {
+                        return Err(rejected(FailureKind::InvalidResponse, &bytes));
+                    }
+                }
+                parsed = Some(match body {
+                    Value::Array(mut calls) if batch.is_some() => calls.swap_remove(0),
+                    body => body,
+                });
+            }
+            Ok(QueryResponse {
+                latency: elapsed,
+                ttfb,
+                status: Some(status.as_u16()),
+                body: parsed,
+                bytes: bytes.len() as u64,
+                retried: false,
+                redirect,
+            })
+        }
+        Err(e) => Err(FailureKind::from_reqwest(&e).into()),
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Delay before the first retry; doubled after every further failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// How a worker reaches its endpoint. Every variant is a cheap, shared handle.
+#[derive(Clone)]
+enum Transport {
+    Http { client: Client, reuse: ConnectionReuse },
+    Grpc(tonic::transport::Channel),
+    Tcp { host: String, port: u16 },
+}
+
+/// A single attempt at `query` over whichever transport the run uses.
+async fn send_query(
+    transport: &Transport,
+    endpoint: &str,
+    query: &str,
+    config: &TestConfig,
+) -> Result<QueryResponse, QueryFailure> {
+    match transport {
+        Transport::Http { client, reuse } => {
+            reuse.requests.fetch_add(1, Ordering::Relaxed);
+            test_endpoint_query(client, endpoint, query, config).await
+        }
+        Transport::Grpc(channel) => {
+            let timeout = query_override(&config.query_timeouts, query).copied();
+            grpc::probe(channel.clone(), query, &config.headers, timeout.unwrap_or(config.timeout))
+                .await
+                .map_err(QueryFailure::from)
+        }
+        Transport::Tcp { host, port } => {
+            let timeout = query_override(&config.query_timeouts, query).copied();
+            tcp::probe(host, *port, config.ip_version, timeout.unwrap_or(config.timeout))
+                .await
+                .map_err(QueryFailure::from)
+        }
+    }
+}
+
+/// Runs a query with up to `config.retries` extra attempts. A retried success
+/// reports the wall time since the first attempt as its latency, so failed
+/// attempts and backoff are included; its TTFB is that of the final attempt.
+async fn test_endpoint_query_with_retries(
+    transport: &Transport,
+    endpoint: &str,
+    query: &str,
+    config: &TestConfig,
+) -> Result<QueryResponse, QueryFailure> {
+    let start = Instant::now();
+    let mut backoff = RETRY_BASE_DELAY;
+    let mut attempt = 0;
+
+    loop {
+        match send_query(transport, endpoint, query, config).await {
+            Ok(response) if attempt == 0 => return Ok(response),
+            Ok(response) => {
+                return Ok(QueryResponse { latency: start.elapsed(), retried: true, ..response });
+            }
+            Err(failure) if attempt < config.retries => {
+                attempt += 1;
+                config.pause(backoff.max(config.retry_after(failure.kind))).await;
+                // A stop cuts the backoff short; the failure stands rather than being retried.
+                if config.stopped() {
+                    return Err(failure);
+                }
+                backoff *= 2;
+            }
+            Err(failure) => return Err(failure),
+        }
+    }
+}
+
+/// One worker: sends requests until the run ends, cycling through `queries`
+/// round-robin (starting at its own offset) and recording each into its own metrics.
+async fn run_continuous_tests(
+    transport: Transport,
+    endpoint: String,
+    queries: Arc<[String]>,
+    config: Arc<TestConfig>,
+    live: Arc<LiveCounters>,
+    worker: usize,
+    run_start: Instant,
+) -> Vec<TestMetrics> {
+    let mut all_metrics: Vec<TestMetrics> = queries.iter().map(|_| TestMetrics::new()).collect();
+    let mut next = worker;
+    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(worker as u64));
+    let measure_from = Instant::now() + config.warmup;
+    let end_time = measure_from + config.duration;
+    if config.jitter > 0.0 {
+        config.pause(config.interval.mul_f64(rng.random_range(0.0..1.0))).await;
+    }
+    
+    while !config.phase_done(end_time, &live) {
+        if worker >= live.limit.load(Ordering::Relaxed) {
+            sleep(ADAPTIVE_PARK).await;
+            continue;
+        }
+        let index = next % queries.len();
+        next += 1;
+        let (query, metrics) = (&queries[index], &mut all_metrics[index]);
+        // Requests that start during warmup only prime connections, DNS and TLS.
+        let started = Instant::now();
+        let warming_up = started < measure_from;
+        if config.sample_rate < 1.0 && rng.random_range(0.0..1.0) >= config.sample_rate {
+            if !warming_up {
+                metrics.skipped_count += 1;
+            }
+            config.pause(config.jittered_interval(&mut rng)).await;
+            continue;
+        }
+        if let Some(cap) = config.max_requests
+            && !warming_up
+            && live.claimed.fetch_add(1, Ordering::Relaxed) >= cap
+        {
+            break;
+        }
+        let window = config.bucket.map(|bucket| {
+            (started.saturating_duration_since(measure_from).as_secs() / bucket.as_secs()) as usize
+        });
+        let sent = expand_query(query, live.sent[index].fetch_add(1, Ordering::Relaxed))
+            .unwrap_or(Cow::Borrowed(query));
+        let permit = match &config.global_limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        };
+        let result = test_endpoint_query_with_retries(&transport, &endpoint, &sent, &config).await;
+        drop(permit);
+        let hold_off = match &result {
+            Err(failure) => config.retry_after(failure.kind),
+            Ok(_) => Duration::ZERO,
+        };
+        
+        match result {
+            _ if warming_up => {}
+            Ok(response) => {
+                debug!(query = %sent, latency_ms = response.latency.as_secs_f64() * 1000.0, status = response.status,
+                       retried = response.retried, "request succeeded");
+                live.success.fetch_add(1, Ordering::Relaxed);
+                if let Some(window) = window {
+                    metrics.record_window(window, Some(response.latency));
+                }
+                metrics.last_success_at = Some(started);
+                metrics.timeline.record(run_start, started, false);
+                if let Some(log) = &config.slow_requests
+                    && log.check(&config, &endpoint, &sent, response.latency)
+                {
+                    metrics.slow_request_count += 1;
+                }
+                metrics.record_slowest(response.latency, started);
+                metrics.record_success(response);
+            }
+            Err(QueryFailure { kind: FailureKind::RateLimited { .. }, status, .. }) => {
+                info!(query = %sent, status, "request rate limited");
+                metrics.record_status(status);
+                metrics.rate_limited_count += 1;
+            }
+            Err(failure) => {
+                let latency = started.elapsed();
+                warn!(query = %sent, kind = failure.kind.as_str(), status = failure.status,
+                      latency_ms = latency.as_secs_f64() * 1000.0, "request failed");
+                if let Some(log) = &config.failure_log {
+                    log.record(&config, &endpoint, &sent, &failure, latency);
+                }
+                live.failure.fetch_add(1, Ordering::Relaxed);
+                if let Some(window) = window {
+                    metrics.record_window(window, None);
+                }
+                metrics.first_failure_at.get_or_insert(started);
+                metrics.timeline.record(run_start, started, true);
+                metrics.record_failure(failure);
+            }
+        }
+        
+        if !hold_off.is_zero() {
+            // Never sleep past the end of a timed run.
+            let hold_off = match config.max_requests {
+                Some(_) => hold_off,
+                None => hold_off.min(end_time.saturating_duration_since(Instant::now())),
+            };
+            config.pause(hold_off).await;
+        } else if !config.interval.is_zero() {
+            config.pause(config.jittered_interval(&mut rng)).await;
+        }
+    }
+    
+    all_metrics
+}
+
+/// How often `sample_connection_phases` opens a fresh connection.
+const CONNECTION_SAMPLE_EVERY: Duration = Duration::from_secs(1);
+
+/// Times DNS, TCP connect and (for https) the TLS handshake on a fresh connection
+/// every `CONNECTION_SAMPLE_EVERY`. Workers reuse pooled connections, so they
+/// rarely pay these costs themselves; sampling separately tells a slow network
+/// path apart from a slow server.
+async fn sample_connection_phases(endpoint: String, config: Arc<TestConfig>, live: Arc<LiveCounters>) -> TestMetrics {
+    let mut metrics = TestMetrics::new();
+    // Through a proxy, a direct connection would time a path the queries never take.
+    if config.proxy.is_some() {
+        return metrics;
+    }
+    let Some((host, port, tls)) = connection_target(&endpoint) else {
+        return metrics;
+    };
+    let Ok(connector) = config.tls_connector() else {
+        return metrics;
+    };
+    let connector = tokio_native_tls::TlsConnector::from(connector);
+    config.pause(config.warmup).await;
+    let end_time = Instant::now() + config.duration;
+
+    while !config.phase_done(end_time, &live) {
+        // A failed phase just skips the sample; the workers already count failures.
+        let _ = time_connection(
+            &host,
+            port,
+            tls.then_some(&connector),
+            config.ip_version,
+            config.timeout,
+            &mut metrics.phases,
+        ).await;
+        // Wait in short steps so the end of the phase is noticed promptly.
+        let next_sample = Instant::now() + CONNECTION_SAMPLE_EVERY;
+        while Instant::now() < next_sample && !config.phase_done(end_time, &live) {
+            sleep(STOP_POLL.min(next_sample.saturating_duration_since(Instant::now()))).await;
+        }
+    }
+    metrics
+}
+
+/// Host, port and whether TLS is used for an http(s), ws(s) or gRPC endpoint URL.
+fn connection_target(endpoint: &str) -> Option<(String, u16, bool)> {
+    let url = Url::parse(endpoint).ok()?;
+    let tls = matches!(url.scheme(), "https" | "wss");
+    let port = url.port().unwrap_or(if tls { 443 } else { 80 });
+    Some((url.host_str()?.to_string(), port, tls))
+}
+
+async fn time_connection(
+    host: &str,
+    port: u16,
+    tls: Option<&tokio_native_tls::TlsConnector>,
+    ip_version: IpVersion,
+    limit: Duration,
+    phases: &mut ConnectionPhases,
+) -> Option<()> {
+    let start = Instant::now();
+    let addr = tokio::time::timeout(limit, tokio::net::lookup_host((host, port)))
+        .await
+        .ok()?
+        .ok()?
+        .find(|addr| ip_version.allows(addr))?;
+    let _ = phases.dns.record(start.elapsed().as_micros() as u64);
+
+    let start = Instant::now();
+    let stream = tokio::time::timeout(limit, tokio::net::TcpStream::connect(addr)).await.ok()?.ok()?;
+    let _ = phases.connect.record(start.elapsed().as_micros() as u64);
+
+    if let Some(connector) = tls {
+        let start = Instant::now();
+        tokio::time::timeout(limit, connector.connect(host, stream)).await.ok()?.ok()?;
+        let _ = phases.tls.record(start.elapsed().as_micros() as u64);
+    }
+    Some(())
+}
+
+/// `--stop-on-healthy`: a query phase is cut short once at least `min_requests`
+/// measured requests were answered and `success_rate` of them succeeded.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthyExit {
+    pub min_requests: u64,
+    pub success_rate: f64,
+}
+
+impl HealthyExit {
+    fn validate(&self) -> Result<(), String> {
+        if self.min_requests == 0 {
+            return Err("--healthy-min-requests must be at least 1".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.success_rate) {
+            return Err(format!("--healthy-success-rate must be between 0 and 1, got {}", self.success_rate));
+        }
+        Ok(())
+    }
+
+    /// Rate-limited requests are left out, as in the failure rate.
+    fn reached(&self, live: &LiveCounters) -> bool {
+        let success = live.success.load(Ordering::Relaxed);
+        let answered = success + live.failure.load(Ordering::Relaxed);
+        answered >= self.min_requests && success as f64 >= self.success_rate * answered as f64
+    }
+}
+
+/// How failures and latency are traded off in the flakiness score.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreWeights {
+    pub failure: f64,
+    /// Weight of the `latency_metric` component.
+    pub latency: f64,
+    /// Weight of latency variation (`latency_cv`, capped at 1.0); 0 by default.
+    pub stability: f64,
+    pub latency_threshold_ms: f64,
+    pub latency_metric: LatencyMetric,
+    /// Multipliers on the transport and application shares of the failure
+    /// rate before `failure` weighs it in; 1.0 each counts every failure alike.
+    pub transport_failure: f64,
+    pub application_failure: f64,
+}
+
+/// What the latency component of the flakiness score measures, each scaled to
+/// 0-1 and averaged over the scored queries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LatencyMetric {
+    /// p99 over `latency_threshold_ms`: how slow the tail is.
+    #[default]
+    P99,
+    /// `latency_cv`: how uneven latency is, pulled up by a few outliers.
+    Cv,
+    /// `latency_mad_ms` over the median: how uneven latency is, ignoring outliers.
+    Mad,
+}
+
+impl ScoreWeights {
+    fn validate(&self) -> Result<(), String> {
+        if self.failure < 0.0
+            || self.latency < 0.0
+            || self.stability < 0.0
+            || self.transport_failure < 0.0
+            || self.application_failure < 0.0
+        {
+            return Err("Score weights must not be negative".to_string());
+        }
+        let sum = self.failure + self.latency + self.stability;
+        if (sum - 1.0).abs() > 1e-6 {
+            return Err(format!(
+                "--failure-weight, --latency-weight and --stability-weight must sum to 1.0 (got {} + {} + {} = {})",
+                self.failure, self.latency, self.stability, sum
+            ));
+        }
+        if self.latency_threshold_ms <= 0.0 {
+            return Err("--latency-threshold-ms must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn calculate_flakiness_score(failure_rate: f64, latency_severity: f64, latency_cv: f64, weights: &ScoreWeights) -> f64 {
+    let instability = latency_cv.min(1.0);
+    
+    let score = (failure_rate * weights.failure)
+        + (latency_severity * weights.latency)
+        + (instability * weights.stability);
+    (score * 100.0).min(100.0)
+}
+
+/// Standard deviation over mean; 0 when nothing was recorded.
+fn coefficient_of_variation(latencies: &Histogram<u64>) -> f64 {
+    let mean = latencies.mean();
+    if latencies.is_empty() || mean == 0.0 {
+        0.0
+    } else {
+        latencies.stdev() / mean
+    }
+}
+
+/// The `latency_metric` component of the score, in 0-1, averaged over `queries`.
+fn latency_severity(queries: &[&QueryResult], weights: &ScoreWeights) -> f64 {
+    if queries.is_empty() {
+        return 0.0;
+    }
+    let average = |value: fn(&QueryResult) -> f64| queries.iter().map(|q| value(q)).sum::<f64>() / queries.len() as f64;
+    let severity = match weights.latency_metric {
+        LatencyMetric::P99 => average(|q| q.p99_latency_ms) / weights.latency_threshold_ms,
+        LatencyMetric::Cv => average(|q| q.latency_cv),
+        LatencyMetric::Mad => average(|q| if q.p50_latency_ms > 0.0 { q.latency_mad_ms / q.p50_latency_ms } else { 0.0 }),
+    };
+    severity.min(1.0)
+}
+
+/// Median absolute deviation from the median latency, in ms: half the requests
+/// are within this distance of the median. Unlike the standard deviation, a
+/// handful of extreme requests barely moves it.
+fn median_absolute_deviation(latencies: &Histogram<u64>) -> f64 {
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    // On the same bucket midpoints as the deviations, so constant latency has none.
+    let median = latencies.median_equivalent(latencies.value_at_quantile(0.5));
+    let mut deviations: Vec<(u64, u64)> = latencies
+        .iter_recorded()
+        .map(|value| (latencies.median_equivalent(value.value_iterated_to()).abs_diff(median), value.count_at_value()))
+        .collect();
+    deviations.sort_unstable();
+    let total = latencies.len();
+    let mut seen = 0;
+    for (deviation, count) in deviations {
+        seen += count;
+        if seen * 2 >= total {
+            return deviation as f64 / 1000.0;
+        }
+    }
+    0.0
+}
+
+/// How a request's latency rates against an Apdex target T.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ApdexZone {
+    /// Up to T.
+    Satisfied,
+    /// Over T, up to 4T.
+    Tolerating,
+    /// Over 4T, or failed.
+    Frustrated,
+}
+
+impl ApdexZone {
+    fn of(latency_us: u64, target_us: u64) -> Self {
+        if latency_us <= target_us {
+            ApdexZone::Satisfied
+        } else if latency_us <= target_us.saturating_mul(4) {
+            ApdexZone::Tolerating
+        } else {
+            ApdexZone::Frustrated
+        }
+    }
+}
+
+/// The Apdex score of successful `latencies` plus `failures`, which are all
+/// frustrated. `None` with nothing answered.
+fn apdex(latencies: &Histogram<u64>, failures: u64, target: Duration) -> Option<f64> {
+    let answered = latencies.len() + failures;
+    if answered == 0 {
+        return None;
+    }
+    let target_us = target.as_micros() as u64;
+    let (mut satisfied, mut tolerating) = (0, 0);
+    for value in latencies.iter_recorded() {
+        let latency = latencies.median_equivalent(value.value_iterated_to());
+        match ApdexZone::of(latency, target_us) {
+            ApdexZone::Satisfied => satisfied += value.count_at_value(),
+            ApdexZone::Tolerating => tolerating += value.count_at_value(),
+            ApdexZone::Frustrated => {}
+        }
+    }
+    Some((satisfied as f64 + tolerating as f64 / 2.0) / answered as f64)
+}
+
+/// Columns in the per-query latency sparkline.
+const SPARKLINE_WIDTH: usize = 24;
+const SPARKLINE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One line of bars over log-spaced buckets from the fastest to the slowest
+/// request, so a long tail or a second mode stands out. `None` when there is
+/// no spread to draw.
+fn latency_sparkline(latencies: &Histogram<u64>) -> Option<String> {
+    let (min, max) = (latencies.min().max(1) as f64, latencies.max() as f64);
+    if latencies.len() < 2 || max <= min {
+        return None;
+    }
+    let span = (max / min).ln();
+    let mut counts = [0u64; SPARKLINE_WIDTH];
+    for value in latencies.iter_recorded() {
+        let position = (value.value_iterated_to().max(1) as f64 / min).ln() / span;
+        let bucket = ((position * SPARKLINE_WIDTH as f64) as usize).min(SPARKLINE_WIDTH - 1);
+        counts[bucket] += value.count_at_value();
+    }
+    let peak = *counts.iter().max()?;
+    let bars = counts
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            _ => SPARKLINE_BARS[((count * SPARKLINE_BARS.len() as u64).div_ceil(peak) - 1) as usize],
+        })
+        .collect::<String>();
+    Some(format!("{:.1}ms {} {:.1}ms (log)", min / 1000.0, bars, max / 1000.0))
+}
+
+/// Most frequent failure kind; ties are broken alphabetically so output is stable.
+fn dominant_failure_kind(kinds: &HashMap<String, u64>) -> Option<(&str, u64)> {
+    kinds
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(kind, count)| (kind.as_str(), *count))
+}
+
+/// A byte count in B, KB, MB or GB (powers of 1024), e.g. `4.8 MB`.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn get_status_emoji(score: f64) -> &'static str {
+    if score < 10.0 {
+        "🟢"
+    } else if score < 30.0 {
+        "🟡"
+    } else if score < 60.0 {
+        "🟠"
+    } else {
+        "🔴"
+    }
+}
+
+impl Transport {
+    fn new(endpoint: &str, config: &TestConfig) -> Self {
+        if config.tcp_only {
+            let (host, port) = tcp::target(endpoint).expect("checked by TestConfig::validate");
+            Transport::Tcp { host, port }
+        } else if config.grpc {
+            Transport::Grpc(grpc::channel(endpoint, config.timeout, config.ca_cert.as_deref(), config.ip_version.local_address())
+                .expect("checked by TestConfig::validate"))
+        } else {
+            let mut builder = Client::builder()
+                .timeout(config.timeout)
+                .redirect(redirect_policy(config))
+                .default_headers(config.headers.clone())
+                .danger_accept_invalid_certs(config.accept_invalid_certs)
+                .local_address(config.ip_version.local_address());
+            if config.fresh_connections {
+                builder = builder.pool_max_idle_per_host(0);
+            }
+            if let Some(pem) = &config.ca_cert {
+                // Checked by `TestConfig::validate`.
+                builder = builder.add_root_certificate(Certificate::from_pem(pem).expect("invalid CA certificate"));
+            }
+            if let Some(proxy) = &config.proxy {
+                builder = builder.proxy(proxy.clone());
+            }
+            let reuse = ConnectionReuse::default();
+            let client = builder.connector_layer(reuse.clone()).build().unwrap();
+            Transport::Http { client, reuse }
+        }
+    }
+
+    /// `ConnectionReuse::rate` of an HTTP transport; `None` for gRPC and TCP.
+    fn connection_reuse_rate(&self) -> Option<f64> {
+        match self {
+            Transport::Http { reuse, .. } => reuse.rate(),
+            _ => None,
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Redirect hops followed by the one request `test_endpoint_query` is
+    /// awaiting. reqwest does not report them on the response, but it runs the
+    /// redirect policy while that request's future is polled, so in its task.
+    static REDIRECT_HOPS: Cell<usize>;
+}
+
+/// Follows up to `max_redirects` hops, counting them in `REDIRECT_HOPS`, or
+/// none at all without `follow_redirects`.
+fn redirect_policy(config: &TestConfig) -> reqwest::redirect::Policy {
+    if !config.follow_redirects {
+        return reqwest::redirect::Policy::none();
+    }
+    let max = config.max_redirects;
+    reqwest::redirect::Policy::custom(move |attempt| {
+        let followed = attempt.previous().len();
+        if followed > max {
+            return attempt.error(format!("more than {} redirects", max));
+        }
+        let _ = REDIRECT_HOPS.try_with(|hops| hops.set(followed));
+        attempt.follow()
+    })
+}
+
+/// Counts the HTTP requests a client sends, retries and warmup included, and
+/// the connections it opens for them. Clones share the counts.
+#[derive(Debug, Clone, Default)]
+struct ConnectionReuse {
+    requests: Arc<AtomicU64>,
+    opened: Arc<AtomicU64>,
+}
+
+impl ConnectionReuse {
+    /// Share of requests sent over a pooled connection rather than a new one.
+    fn rate(&self) -> Option<f64> {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let opened = self.opened.load(Ordering::Relaxed);
+        (requests > 0).then(|| (1.0 - opened as f64 / requests as f64).max(0.0))
+    }
+}
+
+/// Installed with `connector_layer`, so the pool calls it only when it has no
+/// idle connection to hand out.
+impl<S> Layer<S> for ConnectionReuse {
+    type Service = CountConnections<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountConnections { inner, opened: self.opened.clone() }
+    }
+}
+
+/// A connector that counts every connection attempt, failed ones included.
+#[derive(Clone)]
+struct CountConnections<S> {
+    inner: S,
+    opened: Arc<AtomicU64>,
+}
+
+impl<S: Service<R>, R> Service<R> for CountConnections<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(request)
+    }
+}
+
+/// Turns one query's merged metrics into its `QueryResult` and prints its block.
+fn query_result(
+    endpoint: &str,
+    query: &str,
+    final_metrics: &TestMetrics,
+    config: &TestConfig,
+    run_start: Instant,
+) -> QueryResult {
+    let success = final_metrics.success_count;
+    let failure = final_metrics.failure_count;
+    let rate_limited = final_metrics.rate_limited_count;
+    let answered = success + failure;
+    let total = answered + rate_limited;
+    
+    // Rate limiting says more about the caller than the node, so 429s are
+    // left out of the failure rate and hence the flakiness score.
+    let failure_rate = if answered > 0 {
+        failure as f64 / answered as f64
+    } else {
+        0.0
+    };
+    let retry_rate = if answered > 0 {
+        final_metrics.retried_success_count as f64 / answered as f64
+    } else {
+        0.0
+    };
+    let scheduled = total + final_metrics.skipped_count;
+    let achieved_sample_rate =
+        (config.sample_rate < 1.0 && scheduled > 0).then(|| total as f64 / scheduled as f64);
+    let transport_failures = FailureKind::transport_count(&final_metrics.failure_kinds);
+    let (transport_failure_rate, application_failure_rate) = if answered > 0 {
+        (transport_failures as f64 / answered as f64, (failure - transport_failures) as f64 / answered as f64)
+    } else {
+        (0.0, 0.0)
+    };
+    
+    let (p50, p95, p99, avg, min, max) = if !final_metrics.latencies.is_empty() {
+        (
+            final_metrics.latencies.value_at_quantile(0.5) as f64 / 1000.0,
+            final_metrics.latencies.value_at_quantile(0.95) as f64 / 1000.0,
+            final_metrics.latencies.value_at_quantile(0.99) as f64 / 1000.0,
+            final_metrics.latencies.mean() / 1000.0,
+            final_metrics.latencies.min() as f64 / 1000.0,
+            final_metrics.latencies.max() as f64 / 1000.0,
+        )
+    } else {
+        (0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+    };
+    let percentiles: Vec<(String, f64)> = config.percentiles
+        .iter()
+        .map(|p| {
+            let value = if final_metrics.latencies.is_empty() {
+                0.0
+            } else {
+                final_metrics.latencies.value_at_quantile(p / 100.0) as f64 / 1000.0
+            };
+            (format!("p{}", p), value)
+        })
+        .collect();
+    let latency_cv = coefficient_of_variation(&final_metrics.latencies);
+    let latency_mad = median_absolute_deviation(&final_metrics.latencies);
+    let slo_p99_ms = query_override(&config.slo_p99, query).map(|slo| slo.as_secs_f64() * 1000.0);
+    let slo_met = slo_p99_ms.map(|slo| success > 0 && p99 <= slo);
+    let unsupported = success == 0
+        && failure > 0
+        && final_metrics.failure_kinds.get(FailureKind::MethodNotFound.as_str()) == Some(&failure);
+    let low_confidence = success < config.min_samples && !unsupported;
+    let apdex = config.apdex_target.filter(|_| !unsupported).and_then(|target| {
+        apdex(&final_metrics.latencies, failure, target)
+    });
+    let bytes_received = final_metrics.bytes_received;
+    let avg_response_bytes = if success > 0 {
+        bytes_received as f64 / success as f64
+    } else {
+        0.0
+    };
+    let ttfb = &final_metrics.ttfb_latencies;
+    let (ttfb_p50, ttfb_p95, ttfb_p99) = if !ttfb.is_empty() {
+        (
+            ttfb.value_at_quantile(0.5) as f64 / 1000.0,
+            ttfb.value_at_quantile(0.95) as f64 / 1000.0,
+            ttfb.value_at_quantile(0.99) as f64 / 1000.0,
+        )
+    } else {
+        (0.0, 0.0, 0.0)
+    };
+    
+    // Endpoints running side by side finish together, so print each block in
+    // one go and label it with the endpoint it belongs to.
+    let mut lines = Vec::new();
+    if !config.sequential {
+        lines.push(format!("  {} {} @ {}", "✔".bright_white(), query.bright_white(), endpoint.bright_cyan()));
+    }
+    lines.push(format!("    ✓ Success: {} | ✗ Failure: {} | Rate: {:.1}%", 
+             success.to_string().bright_green(),
+             failure.to_string().bright_red(),
+             (failure_rate * 100.0).to_string().bright_yellow()));
+    let latency: Vec<String> = percentiles.iter().map(|(name, value)| format!("{}={:.1}ms", name, value)).collect();
+    lines.push(format!("    Latency: {} cv={:.2} mad={:.1}ms", latency.join(" "), latency_cv, latency_mad));
+    // Like the progress bars, the sparkline is only drawn on a terminal.
+    if config.progress.is_some()
+        && let Some(sparkline) = latency_sparkline(&final_metrics.latencies)
+    {
+        lines.push(format!("    Shape:   {}", sparkline));
+    }
+    lines.push(format!("    TTFB:    p50={:.1}ms p95={:.1}ms p99={:.1}ms", ttfb_p50, ttfb_p95, ttfb_p99));
+    if let (Some(slo), Some(met)) = (slo_p99_ms, slo_met) {
+        let verdict = if met { "met".bright_green() } else { "VIOLATED".bright_red().bold() };
+        lines.push(format!("    SLO: p99 ≤ {:.0}ms {}", slo, verdict));
+    }
+    if let (Some(target), Some(apdex)) = (config.apdex_target, apdex) {
+        lines.push(format!("    Apdex: {:.2} (T={}ms)", apdex, target.as_millis()));
+    }
+    if let Some(expected) = query_override(&config.expectations, query) {
+        let missing = final_metrics.failure_kinds.get(FailureKind::AssertionFailed.as_str()).copied().unwrap_or(0);
+        let expected: Vec<String> = expected.iter().map(|needle| format!("{:?}", needle)).collect();
+        let verdict = if missing == 0 { "all matched".bright_green() } else { format!("{} missing", missing).bright_red() };
+        lines.push(format!("    Expect: {} {}", expected.join(", "), verdict));
+    }
+    if bytes_received > 0 {
+        lines.push(format!("    Received: {} (avg {} per response)",
+                           format_bytes(bytes_received as f64), format_bytes(avg_response_bytes)));
+    }
+    if final_metrics.retried_success_count > 0 {
+        lines.push(format!("    Succeeded after retry: {} ({:.1}% of answered)",
+                           final_metrics.retried_success_count, retry_rate * 100.0));
+    }
+    if let Some(log) = &config.slow_requests
+        && final_metrics.slow_request_count > 0
+    {
+        lines.push(format!("    Slow requests (>{}ms): {}",
+                           log.threshold.as_millis(), final_metrics.slow_request_count.to_string().bright_yellow()));
+    }
+    if let Some(url) = &final_metrics.final_url {
+        lines.push(format!("    Redirected: {} of {} successful responses, up to {} hop(s), last to {}",
+                           final_metrics.redirected_count.to_string().bright_yellow(), success,
+                           final_metrics.redirect_hops, url));
+    }
+    if let Some(achieved) = achieved_sample_rate {
+        lines.push(format!("    Sampled: {:.1}% of {} scheduled requests sent (--sample-rate {})",
+                           achieved * 100.0, total + final_metrics.skipped_count, config.sample_rate));
+    }
+    if let Some((lowest, highest)) = final_metrics.effective_concurrency {
+        lines.push(format!("    Adaptive concurrency: {}-{} workers", lowest, highest));
+    }
+    if unsupported {
+        lines.push(format!("    {} Unsupported: every response was \"method not found\" (left out of the score)",
+                           "⚠".bright_yellow()));
+    } else if let Some((kind, count)) = dominant_failure_kind(&final_metrics.failure_kinds) {
+        lines.push(format!("    Dominant failure: {} ({} of {})", kind.bright_red(), count, failure));
+        lines.push(format!("    Transport: {:.1}% | Application: {:.1}%",
+                           transport_failure_rate * 100.0, application_failure_rate * 100.0));
+    }
+    if let Some(first_failure) = final_metrics.first_failure_at {
+        let last_success = final_metrics.last_success_at.map_or("never".to_string(), |at| {
+            format!("+{:.1}s", at.saturating_duration_since(run_start).as_secs_f64())
+        });
+        lines.push(format!("    First failure: +{:.1}s | Last success: {}",
+                           first_failure.saturating_duration_since(run_start).as_secs_f64(), last_success));
+    }
+    if final_metrics.status_codes.keys().any(|status| *status != 200) {
+        let mut codes: Vec<(&u16, &u64)> = final_metrics.status_codes.iter().collect();
+        codes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let top: Vec<String> = codes.iter().take(3).map(|(status, count)| format!("{}×{}", status, count)).collect();
+        lines.push(format!("    Status codes: {}", top.join(" ")));
+    }
+    if rate_limited > 0 {
+        lines.push(format!("    Rate limited (429): {} (not counted against the score)",
+                           rate_limited.to_string().bright_yellow()));
+    }
+    let bucket_secs = config.bucket.map_or(0, |bucket| bucket.as_secs());
+    let time_buckets: Vec<WindowStats> = final_metrics.windows
+        .iter()
+        .enumerate()
+        .map(|(index, window)| WindowStats {
+            start_secs: index as u64 * bucket_secs,
+            success_count: window.success_count,
+            failure_count: window.failure_count,
+            p99_latency_ms: window.latencies.value_at_quantile(0.99) as f64 / 1000.0,
+        })
+        .collect();
+    if failure > 0 && !time_buckets.is_empty() {
+        let per_window: Vec<String> = time_buckets.iter().map(|w| w.failure_count.to_string()).collect();
+        lines.push(format!("    Failures per {}s window: {}", bucket_secs, per_window.join(" ")));
+    }
+    config.println(&lines.join("\n"));
+    
+    QueryResult {
+        query: query.to_string(),
+        success_count: success,
+        failure_count: failure,
+        total_requests: total,
+        failure_rate,
+        p50_latency_ms: p50,
+        p95_latency_ms: p95,
+        p99_latency_ms: p99,
+        avg_latency_ms: avg,
+        min_latency_ms: min,
+        max_latency_ms: max,
+        percentiles: percentiles.into_iter().collect(),
+        ttfb_p50_ms: ttfb_p50,
+        ttfb_p95_ms: ttfb_p95,
+        ttfb_p99_ms: ttfb_p99,
+        latency_cv,
+        latency_mad_ms: latency_mad,
+        failures_by_kind: final_metrics.failure_kinds.clone(),
+        transport_failure_rate,
+        application_failure_rate,
+        retried_success_count: final_metrics.retried_success_count,
+        slow_request_count: final_metrics.slow_request_count,
+        skipped_count: final_metrics.skipped_count,
+        redirected_count: final_metrics.redirected_count,
+        redirect_count: (final_metrics.redirected_count > 0).then_some(final_metrics.redirect_hops),
+        final_url: final_metrics.final_url.clone(),
+        achieved_sample_rate,
+        retry_rate,
+        rate_limited_count: rate_limited,
+        status_code_breakdown: final_metrics.status_codes.clone(),
+        bytes_received,
+        avg_response_bytes,
+        invalid_response_count: final_metrics.failure_kinds
+            .get(FailureKind::InvalidResponse.as_str())
+            .copied()
+            .unwrap_or(0),
+        assertion_failed_count: final_metrics.failure_kinds
+            .get(FailureKind::AssertionFailed.as_str())
+            .copied()
+            .unwrap_or(0),
+        latency_histogram: config.export_histogram.then(|| {
+            final_metrics.latencies
+                .iter_recorded()
+                .map(|v| HistogramBucket {
+                    value_ms: v.value_iterated_to() as f64 / 1000.0,
+                    count: v.count_at_value(),
+                })
+                .collect()
+        }),
+        time_buckets,
+        slo_p99_ms,
+        slo_met,
+        apdex,
+        relative_p99: None,
+        low_confidence,
+        unsupported,
+        first_failure_offset_ms: final_metrics.first_failure_at.map(|at| offset_ms(run_start, at)),
+        last_success_offset_ms: final_metrics.last_success_at.map(|at| offset_ms(run_start, at)),
+        slowest_requests: {
+            let mut slowest: Vec<(Duration, Instant)> =
+                final_metrics.slowest.iter().map(|Reverse(request)| *request).collect();
+            slowest.sort_by(|a, b| b.cmp(a));
+            slowest
+                .into_iter()
+                .map(|(latency, started)| SlowRequest {
+                    latency_ms: latency.as_secs_f64() * 1000.0,
+                    offset_ms: offset_ms(run_start, started),
+                })
+                .collect()
+        },
+        min_effective_concurrency: final_metrics.effective_concurrency.map(|(lowest, _)| lowest),
+        max_effective_concurrency: final_metrics.effective_concurrency.map(|(_, highest)| highest),
+    }
+}
+
+fn offset_ms(run_start: Instant, at: Instant) -> u64 {
+    at.saturating_duration_since(run_start).as_millis() as u64
+}
+
+/// Runs one phase of an endpoint's test: the query's workers over `queries`
+/// (a single query unless `--interleave`), plus the connection sampler if
+/// `sample_connections` is set. `run_start` anchors the failure timelines. Returns
+/// the merged metrics of each query, in order, and whether `stop_on_healthy`
+/// ended the phase early.
+#[tracing::instrument(name = "query", skip_all, fields(query = %queries.join(",")))]
+async fn run_phase(
+    endpoint: &str,
+    queries: &[String],
+    transport: &Transport,
+    config: &Arc<TestConfig>,
+    sample_connections: bool,
+    run_start: Instant,
+) -> (Vec<TestMetrics>, bool) {
+    let label = match (config.sequential, queries) {
+        (true, [query]) => query.to_string(),
+        (true, _) => format!("{} (interleaved)", queries.join(", ")),
+        (false, [query]) => format!("{} @ {}", query, endpoint),
+        (false, _) => format!("{} (interleaved) @ {}", queries.join(", "), endpoint),
+    };
+    config.println(&format!("  {} Testing query: {}", "→".bright_white(), label.bright_white()));
+    
+    let workers = match queries {
+        [query] => query_override(&config.query_concurrency, query).copied().unwrap_or(config.concurrency),
+        _ => config.concurrency,
+    };
+    let live = Arc::new(LiveCounters {
+        sent: queries.iter().map(|_| AtomicU64::new(0)).collect(),
+        limit: AtomicUsize::new(workers),
+        ..LiveCounters::default()
+    });
+    let mut adaptive = config.adaptive_threshold.map(|threshold| AdaptiveConcurrency::new(threshold, workers));
+    let queries: Arc<[String]> = queries.into();
+    // A JoinSet aborts its workers when dropped, e.g. when a caller drops `run_test`.
+    let mut tasks = JoinSet::new();
+    
+    for worker in 0..workers {
+        tasks.spawn(run_continuous_tests(
+            transport.clone(),
+            endpoint.to_string(),
+            queries.clone(),
+            config.clone(),
+            live.clone(),
+            worker,
+            run_start,
+        ).in_current_span());
+    }
+    
+    // Connection phases are endpoint-wide, so the sampler's metrics go in with the first query's.
+    // In `--tcp-only` mode the queries already time the handshake.
+    if sample_connections && !config.tcp_only {
+        let sampler = sample_connection_phases(endpoint.to_string(), config.clone(), live.clone());
+        tasks.spawn(async move { vec![sampler.await] }.in_current_span());
+    }
+    
+    let mut progress =
+        QueryProgress::start(config, label, config.warmup + config.duration, config.max_requests, live.clone());
+    let mut ticker = tokio::time::interval(PROGRESS_TICK);
+    let mut final_metrics: Vec<TestMetrics> = queries.iter().map(|_| TestMetrics::new()).collect();
+    loop {
+        tokio::select! {
+            result = tasks.join_next() => match result {
+                Some(Ok(worker_metrics)) => {
+                    for (merged, metrics) in final_metrics.iter_mut().zip(worker_metrics) {
+                        merged.merge(metrics);
+                    }
+                }
+                Some(Err(_)) => {}
+                None => break,
+            },
+            _ = ticker.tick() => {
+                progress.tick();
+                if let Some(adaptive) = &mut adaptive {
+                    adaptive.adjust(&live);
+                }
+            }
+        }
+    }
+    progress.finish();
+    if let Some(adaptive) = &adaptive {
+        for metrics in &mut final_metrics {
+            metrics.effective_concurrency = Some((adaptive.lowest, adaptive.highest));
+        }
+    }
+    debug_assert_eq!(
+        final_metrics.iter().map(|m| m.success_count).sum::<u64>(),
+        live.success.load(Ordering::Relaxed)
+    );
+    debug_assert_eq!(
+        final_metrics.iter().map(|m| m.failure_count).sum::<u64>(),
+        live.failure.load(Ordering::Relaxed)
+    );
+    info!(
+        success = live.success.load(Ordering::Relaxed),
+        failure = live.failure.load(Ordering::Relaxed),
+        rate_limited = final_metrics.iter().map(|m| m.rate_limited_count).sum::<u64>(),
+        "query phase finished"
+    );
+    (final_metrics, config.confirmed_healthy(&live))
+}
+
+/// Each query's raw metrics, kept for `TestConfig::aggregate`.
+type QueryMetrics = Vec<(String, TestMetrics)>;
+
+/// Tests one endpoint. The query metrics are empty unless `aggregate` is set.
+#[tracing::instrument(name = "endpoint", skip_all, fields(endpoint = %endpoint, ip_version = config.ip_version.as_str()))]
+async fn test_endpoint(endpoint: &str, config: &Arc<TestConfig>, run_start: Instant) -> (EndpointReport, QueryMetrics) {
+    let family = config.ip_version.label().map(|family| format!(" ({})", family)).unwrap_or_default();
+    config.println(&format!("\n{} Testing endpoint: {}{}", "🔍".bright_blue(), endpoint.bright_cyan(), family));
+    
+    let transport = Transport::new(endpoint, config);
+    
+    let mut query_results = Vec::new();
+    let mut query_metrics = Vec::new();
+    let mut timelines = Vec::new();
+    let mut latest_block_height = None;
+    let (mut latest_block_hash, mut latest_app_hash) = (None, None);
+    let mut phases = ConnectionPhases::new();
+    let mut stopped_early = false;
+    let (queries, excluded_queries): (Vec<String>, Vec<String>) =
+        config.queries.iter().cloned().partition(|query| !config.is_excluded(endpoint, query));
+    // With `--interleave` every query shares one phase, so each samples the full
+    // window. An endpoint with every query excluded gets no phases at all.
+    let phase_size = if config.interleave { queries.len().max(1) } else { 1 };
+    let query_phases: Vec<&[String]> = queries.chunks(phase_size).collect();
+    
+    // With `--parallel-queries` every phase runs at once up front, and only the
+    // first samples connection setup; otherwise phases run one after another.
+    let mut finished = if config.parallel_queries {
+        let runs = query_phases
+            .iter()
+            .enumerate()
+            .map(|(index, queries)| run_phase(endpoint, queries, &transport, config, index == 0, run_start));
+        join_all(runs).await
+    } else {
+        Vec::new()
+    }
+    .into_iter();
+    
+    for (index, queries) in query_phases.into_iter().enumerate() {
+        let (metrics, healthy) = match finished.next() {
+            Some(run) => run,
+            // Queries not reached before a stop are left out of the report.
+            None if config.stopped() => break,
+            None => {
+                if index > 0 {
+                    config.pause(config.query_cooldown).await;
+                }
+                run_phase(endpoint, queries, &transport, config, true, run_start).await
+            }
+        };
+        stopped_early |= healthy;
+        for (query, mut final_metrics) in queries.iter().zip(metrics) {
+            debug_assert_eq!(final_metrics.latencies.len(), final_metrics.success_count);
+            phases.merge(&final_metrics.phases);
+            if query_method(query) == "status"
+                && let Some(status) = &final_metrics.last_response
+            {
+                latest_block_height = block_height(status);
+                latest_block_hash = sync_info_string(status, "latest_block_hash");
+                latest_app_hash = sync_info_string(status, "latest_app_hash");
+            }
+            query_results.push(query_result(endpoint, query, &final_metrics, config, run_start));
+            timelines.push(std::mem::take(&mut final_metrics.timeline));
+            if config.aggregate {
+                query_metrics.push((query.clone(), final_metrics));
+            }
+        }
+    }
+    
+    let websocket = if config.websocket && !config.stopped() {
+        test_websocket(endpoint, config).await
+    } else {
+        None
+    };
+    
+    let report = EndpointReport {
+        fresh_connections: config.fresh_connections,
+        stopped_early,
+        excluded_queries,
+        latest_block_height,
+        latest_block_hash,
+        latest_app_hash,
+        dns_p50_ms: ConnectionPhases::median_ms(&phases.dns),
+        connect_p50_ms: ConnectionPhases::median_ms(&phases.connect),
+        tls_p50_ms: ConnectionPhases::median_ms(&phases.tls),
+        connection_reuse_rate: transport.connection_reuse_rate(),
+        failure_correlation: failure_correlation(&timelines),
+        websocket,
+        ..endpoint_report(endpoint, query_results, config)
+    };
+    (report, query_metrics)
+}
+
+/// Scores `query_results` into a report for `endpoint`. Everything that is not
+/// derived from the query results is left empty for the caller to fill in.
+fn endpoint_report(endpoint: &str, query_results: Vec<QueryResult>, config: &TestConfig) -> EndpointReport {
+    let total_requests: u64 = query_results.iter().map(|r| r.total_requests).sum();
+    let unsupported_queries: Vec<String> =
+        query_results.iter().filter(|r| r.unsupported).map(|r| r.query.clone()).collect();
+    // Queries the node does not serve say nothing about its reliability.
+    let scored: Vec<&QueryResult> = query_results.iter().filter(|r| !r.unsupported).collect();
+    let total_success: u64 = scored.iter().map(|r| r.success_count).sum();
+    let total_failure: u64 = scored.iter().map(|r| r.failure_count).sum();
+    let answered = total_success + total_failure;
+    let overall_failure_rate = if answered > 0 {
+        total_failure as f64 / answered as f64
+    } else {
+        0.0
+    };
+    // A success that took retries still means the node failed at least once.
+    // Up to `retry_budget` of those are forgiven; the rest are scored as failures
+    // so heavy retrying cannot make a flaky node look healthy.
+    let retry_rate = if answered > 0 {
+        scored.iter().map(|r| r.retried_success_count).sum::<u64>() as f64 / answered as f64
+    } else {
+        0.0
+    };
+    let retry_budget_exceeded = retry_rate > config.retry_budget;
+    // `--transport-failure-weight` and `--application-failure-weight` decide
+    // how much each kind of failure counts; at 1.0 each this is the failure rate.
+    let transport_failures: u64 = scored.iter().map(|r| FailureKind::transport_count(&r.failures_by_kind)).sum();
+    let weighted_failure_rate = if answered > 0 {
+        (transport_failures as f64 * config.weights.transport_failure
+            + (total_failure - transport_failures) as f64 * config.weights.application_failure)
+            / answered as f64
+    } else {
+        0.0
+    };
+    let scored_failure_rate = (weighted_failure_rate + (retry_rate - config.retry_budget).max(0.0)).min(1.0);
+    
+    // With no queries there is nothing to average; avoid a NaN score.
+    let avg_cv = if scored.is_empty() {
+        0.0
+    } else {
+        scored.iter().map(|r| r.latency_cv).sum::<f64>() / scored.len() as f64
+    };
+    
+    let latency_severity = latency_severity(&scored, &config.weights);
+    let flakiness_score = calculate_flakiness_score(scored_failure_rate, latency_severity, avg_cv, &config.weights);
+    let classification = Classification::from_failure_rate(overall_failure_rate);
+    info!(score = flakiness_score, failure_rate = overall_failure_rate,
+          classification = classification.as_str(), "endpoint tested");
+    
+    EndpointReport {
+        endpoint: endpoint.to_string(),
+        region: config.regions.get(endpoint).cloned(),
+        overall_success_rate: 1.0 - overall_failure_rate,
+        overall_failure_rate,
+        flakiness_score,
+        smoothed_score: None,
+        classification,
+        ip_version: config.ip_version,
+        fresh_connections: false,
+        stopped_early: false,
+        excluded_queries: Vec::new(),
+        unsupported_queries,
+        retry_rate,
+        retry_budget_exceeded,
+        total_requests,
+        test_duration_secs: config.duration.as_secs(),
+        latest_block_height: None,
+        latest_block_hash: None,
+        latest_app_hash: None,
+        height_lag: None,
+        dns_p50_ms: None,
+        connect_p50_ms: None,
+        tls_p50_ms: None,
+        connection_reuse_rate: None,
+        total_bytes_received: query_results.iter().map(|r| r.bytes_received).sum(),
+        failure_correlation: None,
+        queries: query_results,
+        websocket: None,
+    }
+}
+
+/// Name of the synthetic `TestConfig::aggregate` report.
+const AGGREGATE_ENDPOINT: &str = "ALL";
+
+/// Merges every endpoint's metrics per query, in `--queries` order, and scores
+/// the result like one endpoint. `None` if no endpoint measured anything.
+fn aggregate_report(per_endpoint: Vec<QueryMetrics>, config: &TestConfig, run_start: Instant) -> Option<EndpointReport> {
+    let endpoints = per_endpoint.len();
+    let mut merged: HashMap<String, TestMetrics> = HashMap::new();
+    for (query, metrics) in per_endpoint.into_iter().flatten() {
+        merged.entry(query).or_insert_with(TestMetrics::new).merge(metrics);
+    }
+    if merged.is_empty() {
+        return None;
+    }
+    config.println(&format!("\n{} Aggregate of {} endpoint report(s): {}",
+                            "📊".bright_blue(), endpoints, AGGREGATE_ENDPOINT.bright_cyan()));
+    let query_results = config.queries
+        .iter()
+        .filter_map(|query| merged.get(query).map(|metrics| query_result(AGGREGATE_ENDPOINT, query, metrics, config, run_start)))
+        .collect();
+    Some(endpoint_report(AGGREGATE_ENDPOINT, query_results, config))
+}
+
+/// Runs the `--websocket` phase for one endpoint and prints its block.
+async fn test_websocket(endpoint: &str, config: &TestConfig) -> Option<WebSocketReport> {
+    let label = if config.sequential {
+        "websocket NewBlock".to_string()
+    } else {
+        format!("websocket NewBlock @ {}", endpoint)
+    };
+    config.println(&format!("  {} Testing subscription: {}", "→".bright_white(), label.bright_white()));
+
+    let live = Arc::new(LiveCounters::default());
+    let mut progress = QueryProgress::start(config, label.clone(), config.duration, None, live.clone());
+    let mut ticker = tokio::time::interval(PROGRESS_TICK);
+    let monitor = websocket::monitor(
+        endpoint,
+        &config.headers,
+        config.tls_connector().ok(),
+        config.duration,
+        config.timeout,
+        &live,
+        &config.stop,
+    );
+    tokio::pin!(monitor);
+    let result = loop {
+        tokio::select! {
+            result = &mut monitor => break result,
+            _ = ticker.tick() => progress.tick(),
+        }
+    };
+    progress.finish();
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            warn!(error = %e, "websocket subscription failed");
+            config.eprintln(&e);
+            return None;
+        }
+    };
+
+    let mut lines = Vec::new();
+    if !config.sequential {
+        lines.push(format!("  {} {}", "✔".bright_white(), label.bright_white()));
+    }
+    lines.push(format!("    Blocks: {} | Missed: {} | Drops: {} | Reconnects: {} | Failed connects: {}",
+                       report.blocks_received.to_string().bright_green(),
+                       report.missed_blocks.to_string().bright_red(),
+                       report.connection_drops.to_string().bright_red(),
+                       report.reconnections,
+                       report.failed_connects));
+    lines.push(format!("    Block gap: avg={:.0}ms p99={:.0}ms max={:.0}ms",
+                       report.avg_block_gap_ms, report.p99_block_gap_ms, report.max_block_gap_ms));
+    config.println(&lines.join("\n"));
+    Some(report)
+}
+
+/// `result.sync_info.latest_block_height` from a `status` response. Tendermint
+/// encodes it as a string, but accept a plain number too.
+fn block_height(status: &Value) -> Option<u64> {
+    let height = status.pointer("/result/sync_info/latest_block_height")?;
+    height.as_u64().or_else(|| height.as_str()?.parse().ok())
+}
+
+fn sync_info_string(status: &Value, field: &str) -> Option<String> {
+    status.pointer(&format!("/result/sync_info/{}", field))?.as_str().map(String::from)
+}
+
+/// Whether endpoints that reported the same block height also reported the
+/// same block and app hash. Endpoints at different heights cannot be compared;
+/// `height_lag` covers those.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsensusAgreement {
+    /// Endpoints that shared their height with at least one other endpoint.
+    pub compared_endpoints: usize,
+    /// Endpoints outside the largest group agreeing on the hashes at their
+    /// height; every endpoint at that height when no group is largest.
+    pub diverged_endpoints: Vec<String>,
+    pub divergences: Vec<HeightDivergence>,
+}
+
+/// Endpoints at one height that did not all report the same hashes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeightDivergence {
+    pub height: u64,
+    pub endpoints: Vec<EndpointHashes>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointHashes {
+    pub endpoint: String,
+    pub block_hash: String,
+    pub app_hash: Option<String>,
+}
+
+/// Compares the `status` hashes of endpoints at equal heights; `None` unless
+/// at least two endpoints could be compared.
+pub fn consensus_agreement(reports: &[EndpointReport]) -> Option<ConsensusAgreement> {
+    let mut by_height: BTreeMap<u64, Vec<EndpointHashes>> = BTreeMap::new();
+    for report in reports {
+        if let (Some(height), Some(block_hash)) = (report.latest_block_height, &report.latest_block_hash) {
+            by_height.entry(height).or_default().push(EndpointHashes {
+                endpoint: report.endpoint.clone(),
+                block_hash: block_hash.clone(),
+                app_hash: report.latest_app_hash.clone(),
+            });
+        }
+    }
+
+    let mut agreement = ConsensusAgreement { compared_endpoints: 0, diverged_endpoints: Vec::new(), divergences: Vec::new() };
+    for (height, endpoints) in by_height.into_iter().filter(|(_, endpoints)| endpoints.len() > 1) {
+        agreement.compared_endpoints += endpoints.len();
+        let same = |a: &EndpointHashes, b: &EndpointHashes| a.block_hash == b.block_hash && a.app_hash == b.app_hash;
+        let counts: Vec<usize> = endpoints.iter().map(|e| endpoints.iter().filter(|o| same(e, o)).count()).collect();
+        let largest = counts.iter().copied().max().unwrap_or(0);
+        if largest == endpoints.len() {
+            continue;
+        }
+        // One group of the largest size is the majority; with a tie there is none.
+        let largest_groups = counts.iter().filter(|c| **c == largest).count() / largest;
+        let majority = (largest_groups == 1)
+            .then(|| &endpoints[counts.iter().position(|c| *c == largest).unwrap_or(0)]);
+        agreement.diverged_endpoints.extend(
+            endpoints
+                .iter()
+                .filter(|e| majority.is_none_or(|majority| !same(e, majority)))
+                .map(|e| e.endpoint.clone()),
+        );
+        agreement.divergences.push(HeightDivergence { height, endpoints });
+    }
+    (agreement.compared_endpoints > 0).then_some(agreement)
+}
+
+/// Fills in `relative_p99` for every query that at least two endpoints answered,
+/// against the lowest p99 among them.
+fn compute_relative_p99(reports: &mut [EndpointReport]) {
+    let answered = |q: &QueryResult| q.success_count > 0 && !q.unsupported && q.p99_latency_ms > 0.0;
+    let mut fastest: HashMap<String, (f64, usize)> = HashMap::new();
+    for q in reports.iter().flat_map(|r| &r.queries).filter(|q| answered(q)) {
+        let entry = fastest.entry(q.query.clone()).or_insert((f64::INFINITY, 0));
+        *entry = (entry.0.min(q.p99_latency_ms), entry.1 + 1);
+    }
+    for q in reports.iter_mut().flat_map(|r| &mut r.queries) {
+        if let Some(&(min, count)) = fastest.get(&q.query)
+            && count >= 2
+            && answered(q)
+        {
+            q.relative_p99 = Some(q.p99_latency_ms / min);
+        }
+    }
+}
+
+/// Fills in `height_lag` relative to the highest block seen across all endpoints.
+fn compute_height_lag(reports: &mut [EndpointReport]) {
+    let Some(max_height) = reports.iter().filter_map(|r| r.latest_block_height).max() else {
+        return;
+    };
+    for report in reports {
+        report.height_lag = report.latest_block_height.map(|height| max_height - height);
+    }
+}
+
+/// A run's endpoint reports with when it started and finished. This is what
+/// the CLI writes as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_started_at: DateTime<Utc>,
+    pub run_finished_at: DateTime<Utc>,
+    /// Measured time between the two. It includes warmup and waiting for
+    /// workers to finish, so it can exceed the requested duration.
+    pub wall_clock_secs: f64,
+    /// Whether the run was stopped early via `TestConfig::stop`.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Set when the run had a `TestConfig::global_limit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_concurrency: Option<GlobalConcurrency>,
+    /// Hash agreement between endpoints at the same height; see [`consensus_agreement`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consensus_agreement: Option<ConsensusAgreement>,
+    /// Endpoints left out because they were unreachable at the start (`--skip-unreachable`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_endpoints: Vec<SkippedEndpoint>,
+    pub reports: Vec<EndpointReport>,
+    /// Every endpoint merged into one `ALL` report, with `TestConfig::aggregate`.
+    /// Not ranked or compared with the endpoints themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<EndpointReport>,
+}
+
+/// [`run_test`], timed. A `global_limit` starts afresh, so its stats cover this run only.
+pub async fn run_report(mut config: TestConfig) -> RunReport {
+    let stop = config.stop.clone();
+    config.global_limit = config.global_limit.map(|limit| GlobalLimit::new(limit.max));
+    let global_limit = config.global_limit.clone();
+    let skipped_endpoints = config.skipped_endpoints.clone();
+    let run_started_at = Utc::now();
+    let started = Instant::now();
+    let (reports, aggregate) = run_endpoints(config).await;
+    RunReport {
+        run_started_at,
+        run_finished_at: Utc::now(),
+        wall_clock_secs: started.elapsed().as_secs_f64(),
+        interrupted: stop.load(Ordering::Relaxed),
+        global_concurrency: global_limit.map(|limit| limit.stats()),
+        consensus_agreement: consensus_agreement(&reports),
+        skipped_endpoints,
+        reports,
+        aggregate,
+    }
+}
+
+/// An endpoint [`preflight`] could not connect to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedEndpoint {
+    pub endpoint: String,
+    /// The failure kind, e.g. `connect` for a refused connection or failed DNS lookup.
+    pub reason: String,
+}
+
+/// Sends one request per endpoint, with its first query that is not excluded,
+/// and returns the endpoints it could not connect to at all. Any answer, even
+/// an error status, counts as reachable: only dead endpoints are worth
+/// skipping before a long run.
+pub async fn preflight(config: &TestConfig) -> Vec<SkippedEndpoint> {
+    let config = Arc::new(config.clone());
+    let mut tasks = JoinSet::new();
+    for (index, endpoint) in config.endpoints.iter().enumerate() {
+        let Some(query) = config.queries.iter().find(|query| !config.is_excluded(endpoint, query)) else {
+            continue;
+        };
+        let transport = Transport::new(endpoint, &config);
+        let (endpoint, query, config) = (endpoint.clone(), query.clone(), config.clone());
+        tasks.spawn(async move {
+            let sent = expand_query(&query, 0).unwrap_or(Cow::Borrowed(&query));
+            let outcome = send_query(&transport, &endpoint, &sent, &config).await;
+            let unreachable = match outcome {
+                Err(QueryFailure { kind: kind @ FailureKind::Connect, .. }) => Some(kind),
+                _ => None,
+            };
+            unreachable.map(|kind| (index, SkippedEndpoint { endpoint, reason: kind.as_str().to_string() }))
+        });
+    }
+
+    let mut unreachable = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        unreachable.extend(result.expect("preflight task panicked"));
+    }
+    unreachable.sort_by_key(|(index, _)| *index);
+    unreachable.into_iter().map(|(_, skipped)| skipped).collect()
+}
+
+/// The outcome of a single `check_endpoints` request.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub endpoint: String,
+    pub query: String,
+    /// The latency on success, otherwise the failure kind (and HTTP status, if any).
+    pub outcome: Result<Duration, String>,
+}
+
+/// Sends exactly one request, without retries, for every endpoint and query,
+/// to catch unreachable endpoints and unsupported queries before a timed run.
+/// Results are in endpoint, then query, order.
+pub async fn check_endpoints(config: &TestConfig) -> Vec<CheckResult> {
+    let config = Arc::new(config.clone());
+    let mut tasks = JoinSet::new();
+    for endpoint in &config.endpoints {
+        let transport = Transport::new(endpoint, &config);
+        for query in config.queries.iter().filter(|query| !config.is_excluded(endpoint, query)) {
+            let index = tasks.len();
+            let (transport, endpoint, query, config) =
+                (transport.clone(), endpoint.clone(), query.clone(), config.clone());
+            tasks.spawn(async move {
+                let sent = expand_query(&query, 0).unwrap_or(Cow::Borrowed(&query));
+                let outcome = match send_query(&transport, &endpoint, &sent, &config).await {
+                    Ok(response) => Ok(response.latency),
+                    Err(QueryFailure { kind, status: Some(status), .. }) => Err(format!("{} ({})", kind.as_str(), status)),
+                    Err(QueryFailure { kind, status: None, .. }) => Err(kind.as_str().to_string()),
+                };
+                (index, CheckResult { endpoint, query, outcome })
+            });
+        }
+    }
+
+    let mut indexed = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        indexed.push(result.expect("check task panicked"));
+    }
+    indexed.sort_by_key(|(index, _)| *index);
+    indexed.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Tests every endpoint in `config.endpoints` once, concurrently unless
+/// `sequential` is set, and returns the reports in the order given. Call
+/// [`TestConfig::validate`] first; an invalid gRPC or TCP endpoint panics. After a
+/// stop, endpoints that had not started yet are left out. With
+/// [`IpVersion::Both`] each endpoint gets an IPv4 and an IPv6 report.
+pub async fn run_test(config: TestConfig) -> Vec<EndpointReport> {
+    run_endpoints(config).await.0
+}
+
+/// [`run_test`], plus the `aggregate` report if the config asks for one.
+async fn run_endpoints(config: TestConfig) -> (Vec<EndpointReport>, Option<EndpointReport>) {
+    let config = Arc::new(config);
+    let run_start = Instant::now();
+    let mut reports = Vec::new();
+    let mut metrics = Vec::new();
+    
+    if config.sequential {
+        for endpoint in &config.endpoints {
+            for config in config.per_family() {
+                if config.stopped() {
+                    break;
+                }
+                let (report, query_metrics) = test_endpoint(endpoint, &config, run_start).await;
+                reports.push(report);
+                metrics.push(query_metrics);
+            }
+        }
+    } else {
+        let mut tasks = JoinSet::new();
+        for (index, endpoint) in config.endpoints.iter().enumerate() {
+            let endpoint = endpoint.clone();
+            let config = config.clone();
+            // Families are tested one after another so the endpoint never sees double load.
+            tasks.spawn(async move {
+                let mut family_reports = Vec::new();
+                for config in config.per_family() {
+                    if config.stopped() {
+                        break;
+                    }
+                    family_reports.push(test_endpoint(&endpoint, &config, run_start).await);
+                }
+                (index, family_reports)
+            });
+        }
+        
+        let mut indexed = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            indexed.push(result.expect("endpoint test task panicked"));
+        }
+        // Keep reports in the order the endpoints were given.
+        indexed.sort_by_key(|(index, _)| *index);
+        (reports, metrics) = indexed.into_iter().flat_map(|(_, family_reports)| family_reports).unzip();
+    }
+    
+    compute_height_lag(&mut reports);
+    compute_relative_p99(&mut reports);
+    let aggregate = if config.aggregate { aggregate_report(metrics, &config, run_start) } else { None };
+    (reports, aggregate)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_without_queries_scores_zero() {
+        let report = endpoint_report("http://localhost:26657", Vec::new(), &TestConfig::default());
+        assert!(report.flakiness_score.is_finite());
+        assert_eq!(report.flakiness_score, 0.0);
+        assert_eq!(report.overall_failure_rate, 0.0);
+    }
+
+    fn response(latency_ms: u64) -> QueryResponse {
+        let latency = Duration::from_millis(latency_ms);
+        QueryResponse { latency, ttfb: latency, status: Some(200), body: None, bytes: 10, retried: false, redirect: None }
+    }
+
+    fn failure(kind: FailureKind) -> QueryFailure {
+        QueryFailure { kind, status: Some(503), snippet: None }
+    }
+
+    #[test]
+    fn merged_metrics_sum_worker_totals() {
+        let mut workers: Vec<TestMetrics> = (0..3).map(|_| TestMetrics::new()).collect();
+        for (worker, metrics) in workers.iter_mut().enumerate() {
+            for latency in 1..=10 * (worker as u64 + 1) {
+                metrics.record_success(response(latency));
+            }
+            for _ in 0..worker {
+                metrics.record_failure(failure(FailureKind::Http5xx));
+            }
+            metrics.record_failure(failure(FailureKind::Timeout));
+            metrics.rate_limited_count += 1;
+        }
+        let expected_latencies: u64 = workers.iter().map(|m| m.latencies.len()).sum();
+        let expected_max = workers.iter().map(|m| m.latencies.max()).max().unwrap();
+
+        let mut merged = TestMetrics::new();
+        for metrics in workers {
+            merged.merge(metrics);
+        }
+        assert_eq!(merged.success_count, 10 + 20 + 30);
+        assert_eq!(merged.latencies.len(), expected_latencies);
+        assert_eq!(merged.latencies.len(), merged.success_count);
+        assert_eq!(merged.ttfb_latencies.len(), merged.success_count);
+        assert_eq!(merged.latencies.max(), expected_max);
+        assert_eq!(merged.failure_count, 3 + 3);
+        assert_eq!(merged.failure_kinds["http_5xx"], 3);
+        assert_eq!(merged.failure_kinds["timeout"], 3);
+        assert_eq!(merged.rate_limited_count, 3);
+        assert_eq!(merged.status_codes[&200], 60);
+        assert_eq!(merged.status_codes[&503], 6);
+        assert_eq!(merged.bytes_received, 600);
+    }
+
+    fn weights(failure: f64, latency: f64) -> ScoreWeights {
+        ScoreWeights { failure, latency, ..TestConfig::default().weights }
+    }
+
+    #[test]
+    fn default_weights_are_valid() {
+        assert!(TestConfig::default().weights.validate().is_ok());
+        assert!(weights(1.0, 0.0).validate().is_ok());
+    }
+
+    #[test]
+    fn zero_weights_are_rejected() {
+        let zero = weights(0.0, 0.0);
+        assert!(zero.validate().unwrap_err().contains("must sum to 1.0"));
+        // Were they let through, nothing could raise the score.
+        assert_eq!(calculate_flakiness_score(1.0, 1.0, 1.0, &zero), 0.0);
+    }
+
+    #[test]
+    fn weights_must_sum_to_one() {
+        assert!(weights(0.5, 0.3).validate().unwrap_err().contains("= 0.8"));
+        assert!(weights(0.9, 0.3).validate().is_err());
+        assert!(weights(0.7 + 1e-9, 0.3).validate().is_ok());
+    }
+
+    #[test]
+    fn negative_weights_are_rejected() {
+        assert!(weights(1.5, -0.5).validate().unwrap_err().contains("negative"));
+        assert!(ScoreWeights { transport_failure: -1.0, ..weights(0.7, 0.3) }.validate().is_err());
+    }
+
+    #[test]
+    fn score_is_capped_at_100() {
+        assert_eq!(calculate_flakiness_score(1.0, 1.0, 5.0, &weights(0.7, 0.3)), 100.0);
+        assert_eq!(calculate_flakiness_score(0.0, 0.0, 0.0, &weights(0.7, 0.3)), 0.0);
+    }
+
+    /// A histogram of latencies in microseconds, like `TestMetrics::latencies`.
+    fn histogram(values_us: &[u64]) -> Histogram<u64> {
+        let mut histogram = Histogram::<u64>::new(3).unwrap();
+        for &value in values_us {
+            histogram.record(value).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn cv_of_empty_histogram_is_zero() {
+        assert_eq!(coefficient_of_variation(&histogram(&[])), 0.0);
+    }
+
+    #[test]
+    fn cv_of_constant_latency_is_zero() {
+        assert_eq!(coefficient_of_variation(&histogram(&[5000; 100])), 0.0);
+    }
+
+    #[test]
+    fn cv_of_known_spread() {
+        // Half at 1ms, half at 3ms: mean 2ms, standard deviation 1ms.
+        let values: Vec<u64> = [1000, 3000].repeat(50);
+        assert!((coefficient_of_variation(&histogram(&values)) - 0.5).abs() < 1e-3);
+    }
+
+    fn url(endpoint: &str, query: &str) -> String {
+        query_url(endpoint, query).unwrap().to_string()
+    }
+
+    #[test]
+    fn query_url_keeps_the_endpoint_path() {
+        assert_eq!(url("https://host/rpc", "status"), "https://host/rpc/status");
+        assert_eq!(url("https://host/rpc/", "status"), "https://host/rpc/status");
+        assert_eq!(url("https://host", "status"), "https://host/status");
+        assert_eq!(url("https://host/", "status"), "https://host/status");
+    }
+
+    #[test]
+    fn query_url_carries_endpoint_query_string() {
+        assert_eq!(url("https://host?key=1", "status"), "https://host/status?key=1");
+        assert_eq!(url("https://host/rpc?key=1", "status"), "https://host/rpc/status?key=1");
+        assert_eq!(url("https://host?key=1", "block?height=5"), "https://host/block?height=5&key=1");
+    }
+
+    #[test]
+    fn query_url_rejects_invalid_endpoint() {
+        assert!(query_url("not a url", "status").is_none());
+    }
+
+    #[test]
+    fn mad_of_empty_histogram_is_zero() {
+        assert_eq!(median_absolute_deviation(&histogram(&[])), 0.0);
+    }
+
+    #[test]
+    fn mad_of_known_distribution() {
+        // Median 3ms; deviations 2, 1, 0, 1, 2ms have a median of 1ms.
+        let mad = median_absolute_deviation(&histogram(&[1000, 2000, 3000, 4000, 5000]));
+        assert!((mad - 1.0).abs() < 0.01, "{}", mad);
+        assert_eq!(median_absolute_deviation(&histogram(&[5000; 10])), 0.0);
+    }
+
+    #[test]
+    fn mad_ignores_a_heavy_tail() {
+        // Two 10s stalls among 10ms requests blow up the CV but not the MAD.
+        let mut values = vec![10_000; 98];
+        values.extend([10_000_000; 2]);
+        let latencies = histogram(&values);
+        assert!(median_absolute_deviation(&latencies) < 0.1);
+        assert!(coefficient_of_variation(&latencies) > 1.0);
+    }
+
+    #[test]
+    fn apdex_zone_boundaries() {
+        let target = 20_000;
+        assert_eq!(ApdexZone::of(0, target), ApdexZone::Satisfied);
+        assert_eq!(ApdexZone::of(target, target), ApdexZone::Satisfied);
+        assert_eq!(ApdexZone::of(target + 1, target), ApdexZone::Tolerating);
+        assert_eq!(ApdexZone::of(4 * target, target), ApdexZone::Tolerating);
+        assert_eq!(ApdexZone::of(4 * target + 1, target), ApdexZone::Frustrated);
+    }
+
+    #[test]
+    fn apdex_counts_failures_as_frustrated() {
+        let target = Duration::from_millis(20);
+        assert_eq!(apdex(&histogram(&[]), 0, target), None);
+        assert_eq!(apdex(&histogram(&[]), 3, target), Some(0.0));
+        // Two satisfied, two tolerating, one slow and one failed: (2 + 2/2) / 6.
+        let latencies = histogram(&[10_000, 10_000, 50_000, 50_000, 100_000]);
+        assert_eq!(apdex(&latencies, 1, target), Some(0.5));
+        assert_eq!(apdex(&histogram(&[10_000; 4]), 0, target), Some(1.0));
+    }
+}
@@ -1,331 +1,2767 @@
-use clap::Parser;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::SecondsFormat;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use hdrhistogram::Histogram;
-use reqwest::Client;
+use cosmos_flake_detector::{
+    check_endpoints, format_bytes, get_status_emoji, preflight, response_validators, run_report, CheckResult, Classification,
+    ConsensusAgreement, EndpointReport, FailureLog, GlobalConcurrency, GlobalLimit, HealthyExit, IpVersion, LatencyMetric, Protocol, RequestMethod, RunReport, ScoreWeights, SlowRequestLog, TestConfig, DEFAULT_QUERIES, GRPC_DEFAULT_QUERIES, TCP_DEFAULT_QUERIES,
+};
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, Proxy, Url};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use std::time::Duration;
 use tokio::time::sleep;
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Set once from `--quiet`: nothing goes to stdout, and stderr messages drop
+/// their icons.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// `println!` unless `--quiet` is set.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `symbol` and a space to lead a stderr message, or nothing under `--quiet`.
+fn icon(symbol: &str) -> String {
+    if quiet() {
+        String::new()
+    } else {
+        format!("{} ", symbol)
+    }
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "flake-detector")]
 #[command(about = "Detect flaky Cosmos RPC endpoints with query-specific testing", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Used when no subcommand is given, so `flake-detector -e URL` still runs `test`.
+    #[command(flatten)]
+    test: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "Run the full flake test (the default when no subcommand is given)")]
+    Test(Box<Args>),
+    #[command(about = "Hit health once per endpoint with a short timeout and print an up/down table")]
+    Scan(ScanArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ScanArgs {
+    #[arg(short, long, value_delimiter = ',', help = "Comma-separated list of RPC endpoints to scan")]
+    endpoints: Vec<String>,
+
+    #[arg(long, value_name = "PATH",
+          help = "File with one endpoint per line (blank lines and # comments ignored), merged with --endpoints")]
+    endpoints_file: Option<String>,
+
+    #[arg(short = 't', long, default_value = "2", help = "Request timeout in seconds")]
+    timeout: u64,
+
+    #[arg(short = 'H', long = "header", value_parser = parse_header,
+          help = "Extra HTTP header sent with every request, as \"Key: Value\" (repeatable)")]
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     #[arg(short, long, value_delimiter = ',', help = "Comma-separated list of RPC endpoints to test")]
     endpoints: Vec<String>,
 
-    #[arg(short, long, default_value = "60", help = "Test duration in seconds")]
-    duration: u64,
+    #[arg(long, value_name = "PATH",
+          help = "File with one endpoint per line (blank lines and # comments ignored), merged with --endpoints")]
+    endpoints_file: Option<String>,
+
+    /// Region tags split off `url#region` endpoints, by URL.
+    #[arg(skip)]
+    regions: HashMap<String, String>,
+
+    #[arg(short, long, default_value = "60", help = "Test duration in seconds")]
+    duration: u64,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "duration",
+          help = "Send exactly N measured requests per query (across all workers) instead of running for \
+                  --duration, for comparable sample sizes")]
+    requests: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["duration", "watch", "ramp"],
+          help = "Reproducible benchmark mode: a fixed --requests count per query (default 1000), no jitter, \
+                  warmup, --sample-rate, --adaptive or --stop-on-healthy, so runs against the same node send the same requests")]
+    deterministic: bool,
+
+    #[arg(short = 'w', long, default_value = "0",
+          help = "Warmup in seconds before each query's measured duration; requests sent during warmup \
+                  are not recorded (total runtime per query is warmup + duration)")]
+    warmup: u64,
+
+    #[arg(long, value_name = "MS", default_value = "0", conflicts_with_all = ["interleave", "parallel_queries"],
+          help = "Pause in milliseconds between one query and the next on each endpoint, so a heavy query \
+                  does not skew the next one's metrics (adds to the total runtime)")]
+    query_cooldown: u64,
+
+    #[arg(short, long, value_delimiter = ',', 
+          default_value = DEFAULT_QUERIES,
+          help = "Comma-separated list of RPC queries to test")]
+    queries: Vec<String>,
+
+    #[arg(short, long, value_name = "PATH",
+          help = "Output file path (optional, repeatable); the extension picks each file's format (.json, .ndjson, \
+                  .csv, .md, .prom). \"-\" writes the report to stdout and implies --quiet")]
+    output: Vec<String>,
+
+    /// `--output` paths with the format each one is written in, resolved by main.
+    #[arg(skip)]
+    outputs: Vec<(String, OutputFormat)>,
+
+    #[arg(short = 'f', long, value_enum, default_value = "json",
+          help = "Output format for a single --output, or for \"-\" and paths without a known extension")]
+    format: OutputFormat,
+
+    #[arg(long, requires = "output",
+          help = "Append each run's reports to --output as newline-delimited JSON (one endpoint report \
+                  per line, tagged with run_timestamp) instead of overwriting it")]
+    append: bool,
+
+    #[arg(long, value_name = "BYTES", requires_all = ["append", "watch"],
+          help = "In --watch mode, start a new timestamped --output file once the current one reaches this size")]
+    rotate_bytes: Option<u64>,
+
+    #[arg(long, value_name = "N", requires_all = ["append", "watch"],
+          help = "In --watch mode, start a new timestamped --output file after every N cycles")]
+    rotate_cycles: Option<u32>,
+
+    #[arg(short = 'c', long, default_value = "10", help = "Concurrent requests per endpoint")]
+    concurrency: usize,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_key_value::<usize>, value_name = "QUERY=N",
+          conflicts_with = "interleave",
+          help = "Per-query concurrency overrides, e.g. genesis=2,health=20 (others use --concurrency)")]
+    query_concurrency: Vec<(String, usize)>,
+
+    #[arg(long, value_name = "N",
+          help = "Cap on requests in flight across all endpoints and queries combined; \
+                  the report shows the peak reached and how many requests waited for room")]
+    max_concurrency_global: Option<usize>,
+
+    #[arg(long = "exclude", value_name = "ENDPOINT=QUERIES", value_parser = parse_exclude,
+          help = "Never send these comma-separated queries to ENDPOINT, e.g. https://rpc.example.com=net_info,genesis, \
+                  for methods a node disables on purpose (repeatable)")]
+    exclude: Vec<(String, Vec<String>)>,
+
+    #[arg(short = 't', long, default_value = "5", help = "Request timeout in seconds")]
+    timeout: u64,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_key_value::<u64>, value_name = "QUERY=SECS",
+          help = "Per-query timeout overrides in seconds, e.g. genesis=30,health=2 (others use --timeout)")]
+    query_timeout: Vec<(String, u64)>,
+
+    #[arg(long, value_parser = parse_key_value::<String>, value_name = "QUERY=SUBSTRING",
+          help = "Count a successful response as a failure (assertion_failed) unless its body contains SUBSTRING, \
+                  e.g. status=catching_up (repeatable; a query may have several)")]
+    expect: Vec<(String, String)>,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_key_value::<u64>, value_name = "QUERY=MS",
+          help = "p99 latency SLO per query in milliseconds, e.g. status=500,health=100; each query \
+                  reports slo_met and violations are listed in the summary")]
+    slo_p99: Vec<(String, u64)>,
+
+    #[arg(long, requires = "slo_p99", help = "Exit with status 1 if any query violates its --slo-p99")]
+    fail_on_slo: bool,
+
+    #[arg(long, value_name = "MS",
+          help = "Apdex target T in milliseconds: each query reports apdex, counting requests up to T as \
+                  satisfied, up to 4T as tolerating, and slower ones or failures as frustrated")]
+    apdex_target: Option<u64>,
+
+    #[arg(long, value_name = "MS",
+          help = "Print every successful request slower than MS milliseconds as it happens (at most one \
+                  line per second), and count them per query as slow_request_count")]
+    slow_request_ms: Option<u64>,
+
+    #[arg(long, value_name = "N", default_value = "100",
+          help = "Flag queries with fewer successful responses than this as low_confidence: their percentiles \
+                  rest on too few samples to trust")]
+    min_samples: u64,
+
+    #[arg(long, value_delimiter = ',', default_value = "50,95,99", value_name = "P",
+          help = "Latency percentiles to report, e.g. 50,90,99,99.9 (printed per query and stored \
+                  under \"percentiles\" in the JSON output)")]
+    percentiles: Vec<f64>,
+
+    #[arg(short = 'p', long, value_enum, default_value = "rest",
+          help = "RPC protocol: rest (GET <endpoint>/<query>) or jsonrpc (POST a JSON-RPC 2.0 body to the endpoint root)")]
+    protocol: Protocol,
+
+    #[arg(long, value_enum, default_value = "get", conflicts_with = "grpc",
+          help = "HTTP method for --protocol rest requests, e.g. post for LCD routes that need it")]
+    method: RequestMethod,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_key_value::<String>, value_name = "KEY=VALUE",
+          conflicts_with = "grpc",
+          help = "Query parameters added to every request, e.g. pagination.limit=10,height=100")]
+    query_params: Vec<(String, String)>,
+
+    #[arg(long, value_name = "N", conflicts_with = "grpc",
+          help = "With --protocol jsonrpc, send each request as a batch of N copies of the query and require \
+                  N results; latency covers the whole batch")]
+    batch: Option<usize>,
+
+    #[arg(long, conflicts_with = "protocol",
+          help = "Probe gRPC endpoints (e.g. http://node:9090) instead of Tendermint RPC. \
+                  Queries: health, health?service=<name>, reflection")]
+    grpc: bool,
+
+    #[arg(long, conflicts_with_all = ["grpc", "protocol", "batch", "method", "query_params", "websocket",
+                                      "payload_file", "proxy", "insecure", "no_keepalive"],
+          help = "Only time TCP handshakes (endpoints as host:port or URLs), with no HTTP on top, to separate \
+                  network reachability from application problems. Query: connect")]
+    tcp_only: bool,
+
+    #[arg(short = 'i', long, default_value = "100",
+          help = "Delay in milliseconds between requests of each worker (0 = no delay, saturation test). \
+                  Each query receives at most concurrency x 1000 / interval requests per second")]
+    interval: u64,
+
+    #[arg(short = 'r', long, default_value = "0",
+          help = "Retry a failed request up to N times with exponential backoff (100ms, 200ms, ...) \
+                  before counting it as a failure. A retried success records the total wall time \
+                  of all attempts, backoff included, as its latency")]
+    retries: u32,
+
+    #[arg(long, value_name = "RATE", default_value = "0.05",
+          help = "Share (0-1) of answered requests that may need --retries before the excess \
+                  counts against the flakiness score as failures")]
+    retry_budget: f64,
+
+    #[arg(long, help = "Test endpoints one after another instead of all at once (for constrained bandwidth)")]
+    sequential: bool,
+
+    #[arg(long, help = "Test all queries at once, each worker cycling through them round-robin, so every \
+                        query samples the whole test window instead of its own slice of it")]
+    interleave: bool,
+
+    #[arg(long, conflicts_with = "interleave",
+          help = "Test all queries at once, each with its own pool of --concurrency workers, so an endpoint \
+                  takes one --duration instead of one per query (multiplies the load by the number of queries)")]
+    parallel_queries: bool,
+
+    #[arg(long, default_value = "5",
+          help = "Flag endpoints whose latest block height trails the highest seen by more than this many blocks \
+                  (requires the status query)")]
+    max_lag: u64,
+
+    #[arg(long, value_name = "FACTOR", default_value = "3",
+          help = "Flag queries whose p99 is more than FACTOR times the fastest endpoint's p99 for the same query \
+                  (reported as relative_p99)")]
+    slow_factor: f64,
+
+    #[arg(long, help = "Print one aligned line per endpoint (score, success rate, worst p99), worst first by --sort-by, \
+                        instead of the detailed summary")]
+    compact: bool,
+
+    #[arg(long, help = "Print nothing but errors (to stderr, without colors or emoji); use with --output \
+                        and the exit code in scripts")]
+    quiet: bool,
+
+    #[arg(long, help = "Disable colored output (also off when NO_COLOR is set or stdout is not a terminal)")]
+    no_color: bool,
+
+    #[arg(long, value_enum, default_value = "off",
+          help = "Emit structured log events (one per failed request at warn, per request at debug) on stderr, \
+                  separate from the summary on stdout")]
+    log_level: LogLevel,
+
+    #[arg(long, value_enum, default_value = "text", help = "Format of --log-level events: text or json (one object per line)")]
+    log_format: LogFormat,
+
+    #[arg(long, conflicts_with = "compact",
+          help = "Group the summary by region tag (--endpoints https://rpc.example.com#eu-west) with each \
+                  region's mean score, so nearby and faraway endpoints aren't compared directly")]
+    group_by_region: bool,
+
+    #[arg(long, conflicts_with = "ramp",
+          help = "Also merge every endpoint's latency histograms per query into one synthetic ALL report, \
+                  the experience of a client load-balanced across the whole pool")]
+    aggregate: bool,
+
+    #[arg(long, value_name = "PATH",
+          help = "Append every failed request to PATH as one JSON line (timestamp, endpoint, query, failure \
+                  kind, status, latency and the start of the response body) to line up with node logs")]
+    record_failures: Option<String>,
+
+    #[arg(long, value_name = "BYTES", requires = "record_failures",
+          help = "Stop adding to the --record-failures file once it would grow past BYTES")]
+    record_failures_max_bytes: Option<u64>,
+
+    #[arg(long, value_enum, default_value = "score",
+          help = "Rank endpoints in the summary by score (lowest first), latency (lowest worst-query p99 first) \
+                  or success-rate (highest first)")]
+    sort_by: SortBy,
+
+    #[arg(short = 'H', long = "header", value_parser = parse_header,
+          help = "Extra HTTP header sent with every request, as \"Key: Value\" (repeatable)")]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    #[arg(long, value_name = "TOKEN", value_parser = parse_bearer, conflicts_with = "basic_auth",
+          help = "Send \"Authorization: Bearer TOKEN\"; use env:NAME to read the token from an environment variable")]
+    bearer: Option<HeaderValue>,
+
+    #[arg(long, value_name = "USER:PASS", value_parser = parse_basic_auth,
+          help = "Send HTTP Basic credentials; use env:NAME to read USER:PASS from an environment variable")]
+    basic_auth: Option<HeaderValue>,
+
+    #[arg(long, value_name = "PATH",
+          help = "JSON file with endpoints, queries, duration, concurrency, timeout and headers. \
+                  Precedence: command-line flags > config file > built-in defaults")]
+    config: Option<String>,
+
+    #[arg(long, value_enum,
+          help = "Preset duration, concurrency and queries: quick (5s, -c 2, health,status), standard \
+                  (the defaults) or stress (300s, -c 50, every default query). Flags and --config still override it")]
+    profile: Option<Profile>,
+
+    #[arg(long, value_name = "SCORE",
+          help = "Exit with status 1 if any endpoint's flakiness score exceeds this value (for CI gating)")]
+    fail_threshold: Option<f64>,
+
+    #[arg(long, default_value = "0.7", help = "Weight of the failure rate in the flakiness score")]
+    failure_weight: f64,
+
+    #[arg(long, value_name = "FACTOR", default_value = "1",
+          help = "Multiplier on transport failures (timeouts, refused or reset connections) within the \
+                  failure rate the score uses; lower it to go easier on network path problems")]
+    transport_failure_weight: f64,
+
+    #[arg(long, value_name = "FACTOR", default_value = "1",
+          help = "Multiplier on application failures (HTTP and RPC errors, bad responses) within the \
+                  failure rate the score uses; lower it to go easier on node software problems")]
+    application_failure_weight: f64,
+
+    #[arg(long, default_value = "0.3",
+          help = "Weight of latency (see --latency-metric) in the flakiness score \
+                  (failure, latency and stability weights must sum to 1.0)")]
+    latency_weight: f64,
+
+    #[arg(long, value_enum, default_value = "p99",
+          help = "What the latency part of the score measures: p99 (against --latency-threshold-ms), \
+                  cv (coefficient of variation) or mad (median absolute deviation over the median, robust to outliers)")]
+    latency_metric: LatencyMetric,
+
+    #[arg(long, default_value = "0",
+          help = "Weight of latency variation (coefficient of variation, capped at 1) in the flakiness score")]
+    stability_weight: f64,
+
+    #[arg(long, default_value = "1000", help = "p99 latency in milliseconds that counts as fully degraded")]
+    latency_threshold_ms: f64,
+
+    #[arg(long, value_name = "INTERVAL_SECS",
+          help = "Keep running: repeat the full test cycle, sleeping this many seconds between cycles. \
+                  Ctrl-C stops and prints an aggregate summary across all cycles")]
+    watch: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["watch", "check", "append"],
+          help = "Capacity ramp: run --duration at concurrency 1, then step up by --ramp-step until \
+                  --concurrency, and report where each endpoint's failure rate first exceeds --ramp-threshold")]
+    ramp: bool,
+
+    #[arg(long, value_name = "N", default_value = "1", requires = "ramp",
+          help = "Workers added per --ramp step")]
+    ramp_step: usize,
+
+    #[arg(long, value_name = "RATE", default_value = "0.05", requires = "ramp",
+          help = "Failure rate (0-1) at which --ramp considers an endpoint broken and stops stepping it up")]
+    ramp_threshold: f64,
+
+    #[arg(long, value_name = "ALPHA", default_value = "0.3", requires = "watch",
+          help = "Weight (0-1] of the latest cycle in the smoothed --watch score; lower values smooth more. \
+                  Alerts fire on the smoothed score")]
+    ema_alpha: f64,
+
+    #[arg(long, conflicts_with = "watch",
+          help = "Send one request per endpoint and query, print a reachability table and exit \
+                  (status 1 if any failed) instead of running the timed test")]
+    check: bool,
+
+    #[arg(long, conflicts_with = "check",
+          help = "Send one request per endpoint before the run and abort if any cannot be connected to \
+                  (connection refused, DNS failure) instead of spending the full duration on it")]
+    preflight: bool,
+
+    #[arg(long, conflicts_with = "check",
+          help = "Like --preflight, but skip unreachable endpoints and test the rest; \
+                  the summary and report list what was skipped")]
+    skip_unreachable: bool,
+
+    #[arg(long, value_name = "URL", value_parser = Url::parse,
+          help = "POST a JSON alert to this URL for every endpoint whose score exceeds --alert-threshold \
+                  (after each run or watch cycle)")]
+    alert_webhook: Option<Url>,
+
+    #[arg(long, value_name = "SCORE", default_value = "30", requires = "alert_webhook",
+          help = "Flakiness score above which --alert-webhook fires")]
+    alert_threshold: f64,
+
+    #[arg(long, value_name = "CMD", requires = "watch",
+          help = "Shell command to run when an endpoint's smoothed score crosses --unhealthy-threshold in \
+                  --watch mode. The endpoint and score are passed as $1 and $2 and as FLAKE_ENDPOINT and \
+                  FLAKE_SCORE, never spliced into CMD")]
+    on_unhealthy: Option<String>,
+
+    #[arg(long, value_name = "SCORE", default_value = "30", requires = "on_unhealthy",
+          help = "Smoothed score above which --on-unhealthy runs")]
+    unhealthy_threshold: f64,
+
+    #[arg(long, help = "Include each query's full latency histogram in JSON output as {value_ms, count} buckets")]
+    export_histogram: bool,
+
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..),
+          help = "Split each query's measured duration into windows of this many seconds and report \
+                  per-window success/failure counts and p99 (time_buckets in JSON)")]
+    bucket_secs: Option<u64>,
+
+    #[arg(long, help = "On HTTP 429, wait for the Retry-After duration (in seconds) before that worker's next request")]
+    respect_retry_after: bool,
+
+    #[arg(long, conflicts_with = "grpc",
+          help = "After the queries, hold a NewBlock subscription on <endpoint>/websocket for --duration \
+                  and report block gaps, missed blocks and connection drops")]
+    websocket: bool,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_key_value::<String>, value_name = "NAME=PATH",
+          conflicts_with = "grpc",
+          help = "Test a custom query NAME by POSTing the JSON request body in PATH to the endpoint root, \
+                  e.g. tx_search=payloads/tx_search.json (repeatable; NAME is added to --queries)")]
+    payload_file: Vec<(String, String)>,
+
+    #[arg(long, value_name = "PATH",
+          help = "JSON report from an earlier run (-o, json format) to compare against; exits with status 1 \
+                  if any endpoint or query regressed")]
+    baseline: Option<String>,
+
+    #[arg(long, value_name = "PERCENT", default_value = "20", requires = "baseline",
+          help = "Growth in flakiness score or p99 latency over the baseline, in percent, that counts as a regression")]
+    regression_threshold: f64,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "check",
+          help = "JSON file of per-endpoint limits on flakiness score, worst p99 and success rate, with \
+                  defaults for endpoints it does not list; prints pass/fail per endpoint and exits with \
+                  status 1 if any fails")]
+    thresholds: Option<String>,
+
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["watch", "check", "ramp"],
+          help = "--append history (NDJSON) to add up with this run into each endpoint's lifetime uptime; \
+                  a missing file counts as no history yet")]
+    uptime_from: Option<String>,
+
+    #[arg(long, value_name = "FRACTION", default_value = "0",
+          help = "Randomize each worker's sleep within ±FRACTION of --interval (0-1, e.g. 0.5 for 50-150ms \
+                  at 100ms) and stagger worker start-up, so requests don't fire in synchronized bursts")]
+    jitter: f64,
+
+    #[arg(long, value_name = "FRACTION", default_value = "1",
+          help = "Send only this share (0-1] of scheduled requests, picked at random; skipped ones still wait \
+                  out their --interval, so the cadence is kept and the load drops by the same share")]
+    sample_rate: f64,
+
+    #[arg(long, help = "Seed for --jitter and --sample-rate so request scheduling is reproducible")]
+    seed: Option<u64>,
+
+    #[arg(long, value_name = "URL", value_parser = parse_proxy, conflicts_with = "grpc",
+          help = "Send HTTP queries through this proxy: http://, https://, socks5:// or socks5h:// \
+                  (resolve DNS on the proxy), e.g. socks5h://127.0.0.1:1080")]
+    proxy: Option<Url>,
+
+    #[arg(long, conflicts_with = "grpc",
+          help = "Accept any TLS certificate, including self-signed and expired ones (not for public endpoints)")]
+    insecure: bool,
+
+    #[arg(long, value_name = "PATH",
+          help = "Trust this PEM-encoded root CA in addition to the system roots, e.g. for private validator endpoints")]
+    ca_cert: Option<String>,
+
+    #[arg(long, value_enum, default_value = "auto",
+          help = "Address family for HTTP and gRPC queries: v4, v6, auto, or both to test every endpoint \
+                  over IPv4 and then over IPv6 and flag endpoints that only work over one")]
+    ip_version: IpVersion,
+
+    #[arg(long, conflicts_with = "grpc",
+          help = "Open a new connection for every request instead of reusing pooled ones, to measure \
+                  cold-connection latency (TCP and TLS handshake included) as serverless clients see it")]
+    no_keepalive: bool,
+
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set,
+          conflicts_with_all = ["grpc", "tcp_only"],
+          help = "Follow HTTP redirects (the default); each query then reports how often it was redirected and \
+                  where to. With false, a 3xx response counts as a redirect failure")]
+    follow_redirects: bool,
+
+    #[arg(long, value_name = "N", default_value = "10", conflicts_with_all = ["grpc", "tcp_only"],
+          help = "Longest redirect chain to follow; a longer one counts as a too_many_redirects failure")]
+    max_redirects: usize,
+
+    #[arg(long, help = "End each query's test early once the endpoint has proven healthy (see --healthy-min-requests \
+                        and --healthy-success-rate), to find a good endpoint in a large pool quickly")]
+    stop_on_healthy: bool,
+
+    #[arg(long, value_name = "N", default_value = "100", requires = "stop_on_healthy",
+          help = "Requests a query must have answered before --stop-on-healthy can end it")]
+    healthy_min_requests: u64,
+
+    #[arg(long, value_name = "RATE", default_value = "0.99", requires = "stop_on_healthy",
+          help = "Success rate (0-1) a query must sustain for --stop-on-healthy to end it")]
+    healthy_success_rate: f64,
+
+    #[arg(long, help = "Back off under failures: each query halves the workers allowed to send whenever a second's \
+                        failure rate exceeds --adaptive-threshold, and adds one back per healthy second (AIMD)")]
+    adaptive: bool,
+
+    #[arg(long, value_name = "RATE", default_value = "0.1", requires = "adaptive",
+          help = "Failure rate (0-1) over the last second above which --adaptive backs off")]
+    adaptive_threshold: f64,
+}
+
+/// Settings that may be loaded from `--config`. Everything is optional; values
+/// given on the command line take precedence over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    endpoints: Option<Vec<String>>,
+    queries: Option<Vec<String>>,
+    duration: Option<u64>,
+    concurrency: Option<usize>,
+    timeout: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+}
+
+const SAMPLE_CONFIG: &str = r#"{
+  "endpoints": ["https://rpc.example.com", "https://rpc2.example.com"],
+  "queries": ["health", "status", "abci_info"],
+  "duration": 60,
+  "concurrency": 10,
+  "timeout": 5,
+  "headers": { "X-Api-Key": "your-key" }
+}"#;
+
+/// Colors are for people at a terminal: off with `--no-color`, `--quiet` or
+/// `NO_COLOR` (https://no-color.org), and when stdout is piped unless
+/// `CLICOLOR_FORCE` asks for them. stderr follows the same policy.
+fn use_color(args: &Args) -> bool {
+    let env_set = |name| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if args.no_color || args.quiet || env_set("NO_COLOR") {
+        return false;
+    }
+    std::io::stdout().is_terminal() || env_set("CLICOLOR_FORCE")
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("\n{}{}", icon("❌").bright_red(), message);
+    std::process::exit(2);
+}
+
+/// Overlays `--config` values onto `args` for every setting that was not given
+/// explicitly on the command line.
+fn apply_config_file(args: &mut Args, matches: &ArgMatches, path: &str) {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to read config file {}: {}", path, e)));
+    let file: FileConfig = serde_json::from_str(&contents).unwrap_or_else(|e| {
+        exit_with_error(&format!(
+            "Failed to parse config file {}: {}\n\nExpected a JSON object such as:\n{}",
+            path, e, SAMPLE_CONFIG
+        ))
+    });
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if let Some(endpoints) = file.endpoints.filter(|_| !from_cli("endpoints")) {
+        args.endpoints = endpoints;
+    }
+    if let Some(queries) = file.queries.filter(|_| !from_cli("queries")) {
+        args.queries = queries;
+    }
+    if let Some(duration) = file.duration.filter(|_| !from_cli("duration")) {
+        args.duration = duration;
+    }
+    if let Some(concurrency) = file.concurrency.filter(|_| !from_cli("concurrency")) {
+        args.concurrency = concurrency;
+    }
+    if let Some(timeout) = file.timeout.filter(|_| !from_cli("timeout")) {
+        args.timeout = timeout;
+    }
+    if let Some(headers) = file.headers {
+        // File headers go first so a command-line header with the same name wins.
+        let mut merged = Vec::new();
+        for (name, value) in headers {
+            let header = parse_header(&format!("{}: {}", name, value))
+                .unwrap_or_else(|e| exit_with_error(&format!("Invalid header in config file {}: {}", path, e)));
+            merged.push(header);
+        }
+        merged.append(&mut args.headers);
+        args.headers = merged;
+    }
+}
+
+/// Splits `https://rpc.example.com#eu-west` into the URL and its region tag.
+fn split_region(endpoint: &str) -> (&str, Option<&str>) {
+    match endpoint.split_once('#') {
+        Some((url, region)) if !region.trim().is_empty() => (url.trim(), Some(region.trim())),
+        Some((url, _)) => (url.trim(), None),
+        None => (endpoint, None),
+    }
+}
+
+/// `endpoints` plus those in `file`, without blanks and duplicates (ignoring a
+/// trailing slash and region tag), keeping the first occurrence. Also returns
+/// the region of each tagged endpoint.
+fn collect_endpoints(endpoints: &[String], file: Option<&str>) -> (Vec<String>, HashMap<String, String>) {
+    let from_file = file.map(read_endpoints_file).unwrap_or_default();
+    let mut seen = HashSet::new();
+    let mut unique = Vec::new();
+    let mut regions = HashMap::new();
+    for raw in endpoints.iter().chain(&from_file) {
+        let raw = expand_env(raw).unwrap_or_else(|e| exit_with_error(&format!("Endpoint \"{}\": {}", raw, e)));
+        let (endpoint, region) = split_region(raw.trim());
+        if endpoint.is_empty() || !seen.insert(endpoint.trim_end_matches('/').to_string()) {
+            continue;
+        }
+        if let Some(region) = region {
+            regions.insert(endpoint.to_string(), region.to_string());
+        }
+        unique.push(endpoint.to_string());
+    }
+    (unique, regions)
+}
+
+/// Replaces each `${NAME}` with the environment variable, so credentialed URLs
+/// can stay out of endpoint files and shell history.
+fn expand_env(raw: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or("unterminated ${")? + start;
+        let name = &rest[start + 2..end];
+        if name.is_empty() {
+            return Err("empty ${} variable reference".to_string());
+        }
+        let value = std::env::var(name).map_err(|e| match e {
+            std::env::VarError::NotPresent => format!("environment variable {} is not set", name),
+            std::env::VarError::NotUnicode(_) => format!("environment variable {} is not valid UTF-8", name),
+        })?;
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Reads one endpoint per line, skipping blank lines and `#` comments.
+fn read_endpoints_file(path: &str) -> Vec<String> {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to read endpoints file {}: {}", path, e)));
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn load_payloads(files: &[(String, String)]) -> HashMap<String, Value> {
+    files
+        .iter()
+        .map(|(name, path)| {
+            let contents = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| exit_with_error(&format!("Failed to read payload file {}: {}", path, e)));
+            let body = serde_json::from_str(&contents)
+                .unwrap_or_else(|e| exit_with_error(&format!("Invalid JSON in payload file {}: {}", path, e)));
+            (name.clone(), body)
+        })
+        .collect()
+}
+
+fn load_ca_cert(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| exit_with_error(&format!("Failed to read CA certificate {}: {}", path, e)))
+}
+
+/// Parses a `key=value` pair such as `genesis=30`, splitting at the first `=`.
+fn parse_key_value<T>(raw: &str) -> Result<(String, T), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let (key, value) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got \"{}\"", raw))?;
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid value in \"{}\": {}", raw, e))?;
+    Ok((key.trim().to_string(), value))
+}
+
+fn parse_exclude(raw: &str) -> Result<(String, Vec<String>), String> {
+    let (endpoint, queries) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected ENDPOINT=QUERY,QUERY, got \"{}\"", raw))?;
+    let queries: Vec<String> = queries
+        .split(',')
+        .map(|q| q.trim().to_string())
+        .filter(|q| !q.is_empty())
+        .collect();
+    if queries.is_empty() {
+        return Err(format!("no queries to exclude in \"{}\"", raw));
+    }
+    Ok((endpoint.trim().to_string(), queries))
+}
+
+fn parse_proxy(raw: &str) -> Result<Url, String> {
+    let url = Url::parse(raw).map_err(|e| format!("invalid proxy URL \"{}\": {}", raw, e))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!("unsupported proxy scheme \"{}\": use http, https, socks5 or socks5h", url.scheme()));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("proxy URL \"{}\" has no host", raw));
+    }
+    Ok(url)
+}
+
+/// Reads `env:NAME` values from the environment so secrets stay out of shell history.
+fn resolve_secret(raw: &str) -> Result<String, String> {
+    match raw.strip_prefix("env:") {
+        Some(name) => std::env::var(name).map_err(|_| format!("environment variable {} is not set", name)),
+        None => Ok(raw.to_string()),
+    }
+}
+
+/// An `Authorization` value, marked sensitive so it is never shown in debug output.
+fn authorization(value: String) -> Result<HeaderValue, String> {
+    let mut value = HeaderValue::from_str(&value).map_err(|e| format!("invalid credentials: {}", e))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn parse_bearer(raw: &str) -> Result<HeaderValue, String> {
+    authorization(format!("Bearer {}", resolve_secret(raw)?))
+}
+
+fn parse_basic_auth(raw: &str) -> Result<HeaderValue, String> {
+    let credentials = resolve_secret(raw)?;
+    if !credentials.contains(':') {
+        return Err("expected USER:PASS".to_string());
+    }
+    authorization(format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+}
+
+fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Key: Value\", got \"{}\"", raw))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name \"{}\": {}", name.trim(), e))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| format!("invalid value for header \"{}\": {}", name, e))?;
+    Ok((name, value))
+}
+
+impl From<&Args> for TestConfig {
+    fn from(args: &Args) -> Self {
+        Self {
+            endpoints: args.endpoints.clone(),
+            regions: args.regions.clone(),
+            skipped_endpoints: Vec::new(),
+            quiet: args.quiet,
+            queries: args.queries.clone(),
+            duration: Duration::from_secs(args.duration),
+            max_requests: args.requests,
+            warmup: Duration::from_secs(args.warmup),
+            query_cooldown: Duration::from_millis(args.query_cooldown),
+            concurrency: args.concurrency,
+            query_concurrency: args.query_concurrency.iter().cloned().collect(),
+            excluded_queries: args.exclude.iter().fold(HashMap::new(), |mut excluded, (endpoint, queries)| {
+                excluded.entry(endpoint.clone()).or_insert_with(Vec::new).extend(queries.iter().cloned());
+                excluded
+            }),
+            timeout: Duration::from_secs(args.timeout),
+            query_timeouts: args.query_timeout
+                .iter()
+                .map(|(query, secs)| (query.clone(), Duration::from_secs(*secs)))
+                .collect(),
+            expectations: args.expect.iter().fold(HashMap::new(), |mut expectations, (query, needle)| {
+                expectations.entry(query.clone()).or_insert_with(Vec::new).push(needle.clone());
+                expectations
+            }),
+            apdex_target: args.apdex_target.map(Duration::from_millis),
+            slo_p99: args.slo_p99
+                .iter()
+                .map(|(query, ms)| (query.clone(), Duration::from_millis(*ms)))
+                .collect(),
+            min_samples: args.min_samples,
+            percentiles: args.percentiles.clone(),
+            protocol: args.protocol,
+            method: args.method,
+            query_params: args.query_params.clone(),
+            batch: args.batch,
+            grpc: args.grpc,
+            tcp_only: args.tcp_only,
+            interval: Duration::from_millis(args.interval),
+            retries: args.retries,
+            retry_budget: args.retry_budget,
+            jitter: args.jitter,
+            sample_rate: args.sample_rate,
+            seed: args.seed.unwrap_or_else(rand::random),
+            sequential: args.sequential,
+            interleave: args.interleave,
+            parallel_queries: args.parallel_queries,
+            validators: response_validators(),
+            headers: {
+                let mut headers = args.headers.iter().fold(HeaderMap::new(), |mut headers, (name, value)| {
+                    headers.insert(name.clone(), value.clone());
+                    headers
+                });
+                if let Some(auth) = args.bearer.as_ref().or(args.basic_auth.as_ref()) {
+                    headers.insert(AUTHORIZATION, auth.clone());
+                }
+                headers
+            },
+            weights: ScoreWeights {
+                failure: args.failure_weight,
+                latency: args.latency_weight,
+                stability: args.stability_weight,
+                latency_threshold_ms: args.latency_threshold_ms,
+                latency_metric: args.latency_metric,
+                transport_failure: args.transport_failure_weight,
+                application_failure: args.application_failure_weight,
+            },
+            export_histogram: args.export_histogram,
+            bucket: args.bucket_secs.map(Duration::from_secs),
+            respect_retry_after: args.respect_retry_after,
+            websocket: args.websocket,
+            payloads: load_payloads(&args.payload_file),
+            progress: (!args.quiet && std::io::stdout().is_terminal())
+                .then(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout())),
+            stop: Arc::default(),
+            proxy: args.proxy.as_ref().map(|url| Proxy::all(url.as_str()).expect("checked by parse_proxy")),
+            accept_invalid_certs: args.insecure,
+            ca_cert: args.ca_cert.as_deref().map(load_ca_cert),
+            ip_version: args.ip_version,
+            fresh_connections: args.no_keepalive,
+            follow_redirects: args.follow_redirects,
+            max_redirects: args.max_redirects,
+            stop_on_healthy: args.stop_on_healthy.then_some(HealthyExit {
+                min_requests: args.healthy_min_requests,
+                success_rate: args.healthy_success_rate,
+            }),
+            adaptive_threshold: args.adaptive.then_some(args.adaptive_threshold),
+            global_limit: args.max_concurrency_global.map(GlobalLimit::new),
+            slow_requests: args.slow_request_ms.map(|ms| SlowRequestLog::new(Duration::from_millis(ms))),
+            aggregate: args.aggregate,
+            failure_log: args.record_failures.as_deref().map(|path| {
+                FailureLog::open(path, args.record_failures_max_bytes).unwrap_or_else(|e| {
+                    exit_with_error(&format!("Failed to open --record-failures file {}: {}", path, e))
+                })
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Json,
+    Csv,
+    Prometheus,
+    Markdown,
+}
+
+impl OutputFormat {
+    fn from_extension(path: &str) -> Option<Self> {
+        match std::path::Path::new(path).extension()?.to_str()? {
+            "json" | "ndjson" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "prom" => Some(OutputFormat::Prometheus),
+            "md" => Some(OutputFormat::Markdown),
+            _ => None,
+        }
+    }
+}
+
+/// The format `path` is written in. An explicit `--format` wins for a single
+/// output, as it always has; otherwise the extension decides, and `--format`
+/// (default json) covers stdout and paths without a known extension. With
+/// several outputs, a file whose format cannot be told is an error.
+fn output_format(path: &str, format: OutputFormat, explicit_format: bool, several: bool) -> Result<OutputFormat, String> {
+    if explicit_format && !several {
+        return Ok(format);
+    }
+    match OutputFormat::from_extension(path) {
+        Some(format) => Ok(format),
+        None if is_stdout(path) || !several || explicit_format => Ok(format),
+        None => Err(format!(
+            "Cannot tell the format of --output {} from its extension: use .json, .ndjson, .csv, .md or .prom",
+            path
+        )),
+    }
+}
+
+/// `--log-level`: the most verbose tracing events emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Sends this crate's tracing events to stderr. Dependencies are left out, so
+/// `--log-level debug` is not drowned in HTTP/2 internals. The endpoint and
+/// query spans are kept at every level so each event carries their fields.
+fn init_logging(args: &Args) {
+    if args.log_level == LogLevel::Off {
+        return;
+    }
+    let max_level = LevelFilter::from(args.log_level);
+    let filter = filter_fn(move |meta| {
+        meta.target().starts_with(env!("CARGO_CRATE_NAME")) && (meta.is_span() || max_level >= *meta.level())
+    });
+    let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr).with_ansi(use_color(args));
+    match args.log_format {
+        LogFormat::Text => tracing_subscriber::registry().with(layer.with_filter(filter)).init(),
+        LogFormat::Json => tracing_subscriber::registry().with(layer.json().with_filter(filter)).init(),
+    }
+}
+
+/// `--profile` presets, applied under `--config` and explicit flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Profile {
+    /// A 5 second smoke test of health and status at concurrency 2.
+    Quick,
+    /// The built-in defaults: 60 seconds at concurrency 10.
+    Standard,
+    /// 300 seconds at concurrency 50 over every default query.
+    Stress,
+}
+
+impl Profile {
+    /// Duration in seconds, concurrency and queries.
+    fn settings(self) -> (u64, usize, &'static str) {
+        match self {
+            Profile::Quick => (5, 2, "health,status"),
+            Profile::Standard => (60, 10, DEFAULT_QUERIES),
+            Profile::Stress => (300, 50, DEFAULT_QUERIES),
+        }
+    }
+}
+
+/// Sets the `--profile` values for every setting that was not given
+/// explicitly on the command line. Runs before `apply_config_file`, so a
+/// config file overrides the profile too.
+fn apply_profile(args: &mut Args, matches: &ArgMatches, profile: Profile) {
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let (duration, concurrency, queries) = profile.settings();
+    if !from_cli("duration") {
+        args.duration = duration;
+    }
+    if !from_cli("concurrency") {
+        args.concurrency = concurrency;
+    }
+    // gRPC and TCP modes swap in their own default queries later.
+    if !from_cli("queries") && !args.grpc && !args.tcp_only {
+        args.queries = queries.split(',').map(str::to_string).collect();
+    }
+}
+
+/// `--requests` for `--deterministic` runs that do not set it.
+const DETERMINISTIC_REQUESTS: u64 = 1000;
+
+/// `--deterministic`: turns off everything that makes the request pattern
+/// depend on timing or randomness. Settings that would undo it are switched
+/// off with a warning rather than rejected, so the mode can be added to an
+/// existing command line.
+fn apply_deterministic(args: &mut Args) {
+    let mut overridden = Vec::new();
+    args.requests.get_or_insert(DETERMINISTIC_REQUESTS);
+    if args.jitter != 0.0 {
+        args.jitter = 0.0;
+        overridden.push("--jitter");
+    }
+    if args.warmup != 0 {
+        args.warmup = 0;
+        overridden.push("--warmup");
+    }
+    if args.sample_rate != 1.0 {
+        args.sample_rate = 1.0;
+        overridden.push("--sample-rate");
+    }
+    if args.adaptive {
+        args.adaptive = false;
+        overridden.push("--adaptive");
+    }
+    if args.stop_on_healthy {
+        args.stop_on_healthy = false;
+        overridden.push("--stop-on-healthy");
+    }
+    if !overridden.is_empty() {
+        eprintln!("{}--deterministic ignores {}", icon("⚠").bright_yellow(), overridden.join(", "));
+    }
+}
+
+/// How `--sort-by` ranks endpoints in the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortBy {
+    /// Lowest flakiness score first.
+    Score,
+    /// Lowest worst-query p99 first.
+    Latency,
+    /// Highest success rate first.
+    SuccessRate,
+}
+
+/// The slowest query's p99, which is what a client mixing queries waits on.
+fn worst_p99(report: &EndpointReport) -> f64 {
+    report.queries.iter().map(|q| q.p99_latency_ms).fold(0.0, f64::max)
+}
+
+/// Reports paired with their 1-based rank, best first. Ties keep input order.
+fn ranked(reports: &[EndpointReport], sort_by: SortBy) -> Vec<(usize, &EndpointReport)> {
+    let mut sorted: Vec<&EndpointReport> = reports.iter().collect();
+    // An endpoint that never answered has no latency, which must not rank as fast.
+    let latency = |report: &EndpointReport| {
+        if report.queries.iter().all(|q| q.success_count == 0) {
+            f64::INFINITY
+        } else {
+            worst_p99(report)
+        }
+    };
+    match sort_by {
+        SortBy::Score => sorted.sort_by(|a, b| a.flakiness_score.total_cmp(&b.flakiness_score)),
+        SortBy::Latency => sorted.sort_by(|a, b| latency(a).total_cmp(&latency(b))),
+        SortBy::SuccessRate => sorted.sort_by(|a, b| b.overall_success_rate.total_cmp(&a.overall_success_rate)),
+    }
+    sorted.into_iter().enumerate().map(|(index, report)| (index + 1, report)).collect()
+}
+
+fn print_results(args: &Args, run: &RunReport) {
+    let ranked = ranked(&run.reports, args.sort_by);
+    let consensus = run.consensus_agreement.as_ref();
+    if args.compact {
+        print_compact_summary(&ranked);
+        if let Some(consensus) = consensus {
+            print_divergences(consensus);
+        }
+    } else {
+        print_summary(&run.reports, &ranked, consensus, args.max_lag, args.slow_factor, args.group_by_region);
+    }
+    if let Some(aggregate) = &run.aggregate {
+        print_aggregate(aggregate, run.reports.len());
+    }
+    if let Some(global) = &run.global_concurrency {
+        print_global_concurrency(global);
+    }
+    if !run.skipped_endpoints.is_empty() {
+        say!("\n{} Skipped, unreachable at start (--skip-unreachable):", "⏭".bright_yellow());
+        for skipped in &run.skipped_endpoints {
+            say!("  {} ({})", skipped.endpoint.bright_cyan(), skipped.reason);
+        }
+    }
+}
+
+/// `--aggregate`: the pool seen as one endpoint, kept apart from the ranking.
+fn print_aggregate(aggregate: &EndpointReport, endpoints: usize) {
+    say!("\n{} {} ({} endpoint reports merged) - Flakiness Score: {:.1}/100",
+             get_status_emoji(aggregate.flakiness_score),
+             aggregate.endpoint.bright_cyan().bold(),
+             endpoints,
+             aggregate.flakiness_score);
+    say!("  Success Rate: {:.1}% | Total Requests: {}",
+             aggregate.overall_success_rate * 100.0,
+             aggregate.total_requests);
+    for q in &aggregate.queries {
+        say!("  {}: p50={:.1}ms p95={:.1}ms p99={:.1}ms | failure rate {:.1}%",
+                 q.query.bright_white(),
+                 q.p50_latency_ms,
+                 q.p95_latency_ms,
+                 q.p99_latency_ms,
+                 q.failure_rate * 100.0);
+    }
+}
+
+/// `--max-concurrency-global`: whether the cap, rather than `--concurrency`, set the pace.
+fn print_global_concurrency(global: &GlobalConcurrency) {
+    if global.requests_waited > 0 {
+        say!("\n{} Global concurrency cap reached: peak {}/{} in flight, {} requests waited for a slot",
+                 "⚠".bright_yellow(), global.peak_in_flight, global.limit, global.requests_waited);
+    } else {
+        say!("\nGlobal concurrency: peak {}/{} in flight, cap never reached",
+                 global.peak_in_flight, global.limit);
+    }
+}
+
+/// Endpoints that reported different hashes at the same height.
+fn print_divergences(consensus: &ConsensusAgreement) {
+    for divergence in &consensus.divergences {
+        say!("\n{} Chain state disagrees at height {} (possible fork or stale node):",
+                 "⚠".bright_red(), divergence.height);
+        for hashes in &divergence.endpoints {
+            let marker = if consensus.diverged_endpoints.contains(&hashes.endpoint) { "✗".bright_red() } else { "✓".bright_green() };
+            say!("  {} {} block={} app={}", marker, hashes.endpoint.bright_cyan(), hashes.block_hash,
+                     hashes.app_hash.as_deref().unwrap_or("-"));
+        }
+    }
+}
+
+/// `--compact`: one line per endpoint, worst first.
+fn print_compact_summary(ranked: &[(usize, &EndpointReport)]) {
+    // The status emoji is two columns wide.
+    let smoothed = ranked.iter().any(|(_, report)| report.smoothed_score.is_some());
+    let smoothed_header = if smoothed { format!("  {:>6}", "EMA") } else { String::new() };
+    say!("\n   {:>6}{}  {:>8}  {:>10}  Endpoint", "Score", smoothed_header, "Success", "p99");
+    for &(_, report) in ranked.iter().rev() {
+        let p99 = worst_p99(report);
+        let smoothed = report.smoothed_score.map(|score| format!("  {:>6.1}", score)).unwrap_or_default();
+        say!("{} {:>6.1}{}  {:>7.1}%  {:>8.1}ms  {}",
+                 get_status_emoji(report.flakiness_score),
+                 report.flakiness_score,
+                 smoothed,
+                 report.overall_success_rate * 100.0,
+                 p99,
+                 endpoint_label(report).bright_cyan());
+    }
+}
+
+fn print_summary(
+    reports: &[EndpointReport],
+    ranked: &[(usize, &EndpointReport)],
+    consensus: Option<&ConsensusAgreement>,
+    max_lag: u64,
+    slow_factor: f64,
+    by_region: bool,
+) {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", "           FLAKINESS DETECTION SUMMARY".bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
+    
+    if by_region {
+        print_region_groups(ranked, max_lag, slow_factor);
+    } else {
+        print_classification_groups(ranked, max_lag, slow_factor);
+    }
+    
+    // `--ip-version both`: endpoints that only work over one family.
+    for v4 in reports.iter().filter(|r| r.ip_version == IpVersion::V4) {
+        let v6 = reports.iter().find(|r| r.endpoint == v4.endpoint && r.ip_version == IpVersion::V6);
+        if let Some(v6) = v6
+            && v6.classification != v4.classification
+        {
+            say!("\n{} {} is {} over IPv4 but {} over IPv6",
+                     "⚠".bright_yellow(),
+                     v4.endpoint.bright_cyan(),
+                     v4.classification.as_str(),
+                     v6.classification.as_str());
+        }
+    }
+    if let Some(consensus) = consensus {
+        print_divergences(consensus);
+    }
+    
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+}
+
+/// Dead endpoints first: they need a different fix than flaky ones. Within a
+/// group endpoints are listed by rank.
+fn print_classification_groups(ranked: &[(usize, &EndpointReport)], max_lag: u64, slow_factor: f64) {
+    for class in [Classification::Down, Classification::Flaky, Classification::Healthy] {
+        let group: Vec<&(usize, &EndpointReport)> = ranked.iter().filter(|(_, r)| r.classification == class).collect();
+        if !group.is_empty() {
+            let heading = format!("{} ({})", class.as_str().to_uppercase(), group.len());
+            let heading = match class {
+                Classification::Down => heading.bright_red(),
+                Classification::Flaky => heading.bright_yellow(),
+                Classification::Healthy => heading.bright_green(),
+            };
+            say!("\n{}", heading.bold());
+            for &&(rank, report) in &group {
+                print_endpoint_summary(report, rank, max_lag, slow_factor);
+            }
+        }
+    }
+}
+
+/// `--group-by-region`: one group per region tag in alphabetical order, then
+/// untagged endpoints, each headed by the group's mean score and success rate.
+fn print_region_groups(ranked: &[(usize, &EndpointReport)], max_lag: u64, slow_factor: f64) {
+    let mut regions: Vec<Option<&str>> = ranked.iter().map(|(_, r)| r.region.as_deref()).collect();
+    // `None` sorts first, so order by "is untagged" before the name.
+    regions.sort_by_key(|region| (region.is_none(), *region));
+    regions.dedup();
+    for region in regions {
+        let group: Vec<&(usize, &EndpointReport)> = ranked.iter().filter(|(_, r)| r.region.as_deref() == region).collect();
+        let count = group.len() as f64;
+        let mean_score = group.iter().map(|(_, r)| r.flakiness_score).sum::<f64>() / count;
+        let success_rate = group.iter().map(|(_, r)| r.overall_success_rate).sum::<f64>() / count;
+        say!("\n{}", format!("{} ({}) - mean score {:.1}/100, mean success rate {:.1}%",
+                                 region.unwrap_or("untagged").to_uppercase(),
+                                 group.len(),
+                                 mean_score,
+                                 success_rate * 100.0).bright_white().bold());
+        for &&(rank, report) in &group {
+            print_endpoint_summary(report, rank, max_lag, slow_factor);
+        }
+    }
+}
+
+/// The endpoint URL, followed by the address family when one was forced.
+fn endpoint_label(report: &EndpointReport) -> String {
+    match report.ip_version.label() {
+        Some(family) => format!("{} ({})", report.endpoint, family),
+        None => report.endpoint.clone(),
+    }
+}
+
+/// `connection_reuse_rate` below which the summary warns about connections being closed.
+const LOW_CONNECTION_REUSE: f64 = 0.8;
+
+/// `failure_correlation` at or above which failures read as node-wide, and at
+/// or below which they read as query-specific.
+const CORRELATED_FAILURES: f64 = 0.5;
+const INDEPENDENT_FAILURES: f64 = 0.2;
+
+fn print_endpoint_summary(report: &EndpointReport, rank: usize, max_lag: u64, slow_factor: f64) {
+    let emoji = get_status_emoji(report.flakiness_score);
+    let score_color = if report.flakiness_score < 10.0 {
+        report.flakiness_score.to_string().bright_green()
+    } else if report.flakiness_score < 30.0 {
+        report.flakiness_score.to_string().bright_yellow()
+    } else if report.flakiness_score < 60.0 {
+        report.flakiness_score.to_string().bright_magenta()
+    } else {
+        report.flakiness_score.to_string().bright_red()
+    };
+    
+    say!("\n#{} {} {} - Flakiness Score: {}/100",
+             rank,
+             emoji,
+             endpoint_label(report).bright_cyan(),
+             score_color.bold());
+    if let Some(smoothed) = report.smoothed_score {
+        say!("  Smoothed Score: {:.1}/100 (moving average across cycles)", smoothed);
+    }
+    say!("  Success Rate: {:.1}% | Total Requests: {}",
+             (report.overall_success_rate * 100.0).to_string().bright_green(),
+             report.total_requests);
+    if report.stopped_early {
+        say!("  {} Confirmed healthy early (--stop-on-healthy)", "✓".bright_green());
+    }
+    if !report.excluded_queries.is_empty() {
+        say!("  Excluded: {} (--exclude)", report.excluded_queries.join(", "));
+    }
+    if !report.unsupported_queries.is_empty() {
+        say!("  {} Unsupported: {} (method not found, left out of the score)",
+                 "⚠".bright_yellow(), report.unsupported_queries.join(", "));
+    }
+    if report.retry_budget_exceeded {
+        say!("  {} Retried: {:.1}% of answered requests, over --retry-budget (excess scored as failures)",
+                 "⚠".bright_yellow(), report.retry_rate * 100.0);
+    }
+    if report.total_bytes_received > 0 {
+        say!("  Received: {}", format_bytes(report.total_bytes_received as f64));
+    }
+    let worst = report.queries
+        .iter()
+        .filter_map(|q| q.slowest_requests.first().map(|slow| (q, slow)))
+        .max_by(|(_, a), (_, b)| a.latency_ms.total_cmp(&b.latency_ms));
+    if let Some((q, slow)) = worst {
+        say!("  Slowest request: {:.1}ms ({} at +{:.1}s)", slow.latency_ms, q.query, slow.offset_ms as f64 / 1000.0);
+    }
+    if let (Some(dns), Some(connect)) = (report.dns_p50_ms, report.connect_p50_ms) {
+        let tls = report.tls_p50_ms.map(|tls| format!(" tls={:.1}ms", tls)).unwrap_or_default();
+        say!("  Connection setup (p50): dns={:.1}ms connect={:.1}ms{}", dns, connect, tls);
+    }
+    for q in report.queries.iter() {
+        if let (Some(hops), Some(url)) = (q.redirect_count, &q.final_url) {
+            say!("  {} Redirected: {} ends up at {} ({} hop(s)), not the endpoint as given",
+                     "⚠".bright_yellow(), q.query, url, hops);
+        }
+    }
+    // With --no-keepalive every request opens a connection by design.
+    if let Some(reuse) = report.connection_reuse_rate.filter(|_| !report.fresh_connections) {
+        if reuse < LOW_CONNECTION_REUSE {
+            say!("  {} Connection reuse: {:.1}% of requests used a pooled connection; the server or a proxy \
+                  may be closing keep-alive connections early", "⚠".bright_yellow(), reuse * 100.0);
+        } else {
+            say!("  Connection reuse: {:.1}% of requests used a pooled connection", reuse * 100.0);
+        }
+    }
+    if let Some(correlation) = report.failure_correlation {
+        let reading = if correlation >= CORRELATED_FAILURES {
+            "failures hit queries together, likely node-wide"
+        } else if correlation <= INDEPENDENT_FAILURES {
+            "failures are independent, likely query-specific"
+        } else {
+            "failures partly overlap across queries"
+        };
+        say!("  Failure correlation: {:.2} ({})", correlation, reading);
+    }
+    for q in report.queries.iter().filter(|q| q.slo_met == Some(false)) {
+        say!("  {} SLO violated: {} p99={:.1}ms (target {:.0}ms)",
+                 "❌".bright_red(),
+                 q.query.bright_white(),
+                 q.p99_latency_ms,
+                 q.slo_p99_ms.unwrap_or_default());
+    }
+    for q in report.queries.iter().filter(|q| q.relative_p99.is_some_and(|relative| relative > slow_factor)) {
+        say!("  {} Slow: {} p99={:.1}ms is {:.1}x the fastest endpoint's",
+                 "⚠".bright_yellow(),
+                 q.query.bright_white(),
+                 q.p99_latency_ms,
+                 q.relative_p99.unwrap_or_default());
+    }
+    for q in report.queries.iter().filter(|q| q.low_confidence) {
+        say!("  {} Low confidence: {} has only {} successful samples, so its percentiles are unreliable",
+                 "⚠".bright_yellow(),
+                 q.query.bright_white(),
+                 q.success_count);
+    }
+    if let Some(lag) = report.height_lag.filter(|lag| *lag > max_lag) {
+        say!("  {} Lagging {} blocks behind the highest endpoint (height {})",
+                 "⚠".bright_yellow(),
+                 lag.to_string().bright_red(),
+                 report.latest_block_height.unwrap_or_default());
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per (endpoint, query) pair, suitable for spreadsheets and CSV datasources.
+fn render_csv(reports: &[EndpointReport]) -> String {
+    let mut out = String::from(
+        "endpoint,query,success_count,failure_count,total_requests,failure_rate,\
+         p50_latency_ms,p95_latency_ms,p99_latency_ms,avg_latency_ms,min_latency_ms,max_latency_ms,\
+         ttfb_p50_ms,ttfb_p95_ms,ttfb_p99_ms,latency_cv,latency_mad_ms,relative_p99,ip_version,bytes_received\n",
+    );
+
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                csv_field(&report.endpoint),
+                csv_field(&q.query),
+                q.success_count,
+                q.failure_count,
+                q.total_requests,
+                q.failure_rate,
+                q.p50_latency_ms,
+                q.p95_latency_ms,
+                q.p99_latency_ms,
+                q.avg_latency_ms,
+                q.min_latency_ms,
+                q.max_latency_ms,
+                q.ttfb_p50_ms,
+                q.ttfb_p95_ms,
+                q.ttfb_p99_ms,
+                q.latency_cv,
+                q.latency_mad_ms,
+                q.relative_p99.map(|relative| relative.to_string()).unwrap_or_default(),
+                report.ip_version.as_str(),
+                q.bytes_received,
+            ));
+        }
+    }
+    out
+}
+
+/// Escapes a label value per the Prometheus text exposition format.
+fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The `endpoint` label, plus `ip_version` when a family was forced.
+fn prometheus_target(report: &EndpointReport) -> String {
+    let endpoint = format!("endpoint=\"{}\"", prometheus_label(&report.endpoint));
+    match report.ip_version {
+        IpVersion::Auto => endpoint,
+        family => format!("{},ip_version=\"{}\"", endpoint, family.as_str()),
+    }
+}
+
+fn render_prometheus(reports: &[EndpointReport]) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP flake_score Endpoint flakiness score (0-100).\n");
+    out.push_str("# TYPE flake_score gauge\n");
+    for report in reports {
+        out.push_str(&format!(
+            "flake_score{{{}}} {}\n",
+            prometheus_target(report),
+            report.flakiness_score
+        ));
+    }
+
+    if reports.iter().any(|r| r.smoothed_score.is_some()) {
+        out.push_str("# HELP flake_smoothed_score Moving average of the flakiness score across watch cycles.\n");
+        out.push_str("# TYPE flake_smoothed_score gauge\n");
+        for report in reports {
+            if let Some(smoothed) = report.smoothed_score {
+                out.push_str(&format!("flake_smoothed_score{{{}}} {}\n", prometheus_target(report), smoothed));
+            }
+        }
+    }
+
+    if reports.iter().any(|r| r.connection_reuse_rate.is_some()) {
+        out.push_str("# HELP flake_connection_reuse_rate Fraction of HTTP requests sent over a pooled connection.\n");
+        out.push_str("# TYPE flake_connection_reuse_rate gauge\n");
+        for report in reports {
+            if let Some(reuse) = report.connection_reuse_rate {
+                out.push_str(&format!("flake_connection_reuse_rate{{{}}} {}\n", prometheus_target(report), reuse));
+            }
+        }
+    }
+
+    out.push_str("# HELP flake_requests_total Requests sent per query.\n");
+    out.push_str("# TYPE flake_requests_total counter\n");
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "flake_requests_total{{{},query=\"{}\"}} {}\n",
+                prometheus_target(report),
+                prometheus_label(&q.query),
+                q.total_requests
+            ));
+        }
+    }
+
+    out.push_str("# HELP flake_bytes_received_total Response body bytes of successful requests per query.\n");
+    out.push_str("# TYPE flake_bytes_received_total counter\n");
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "flake_bytes_received_total{{{},query=\"{}\"}} {}\n",
+                prometheus_target(report),
+                prometheus_label(&q.query),
+                q.bytes_received
+            ));
+        }
+    }
+
+    out.push_str("# HELP flake_failure_rate Fraction of failed requests per query.\n");
+    out.push_str("# TYPE flake_failure_rate gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "flake_failure_rate{{{},query=\"{}\"}} {}\n",
+                prometheus_target(report),
+                prometheus_label(&q.query),
+                q.failure_rate
+            ));
+        }
+    }
+
+    out.push_str("# HELP flake_latency_ms Request latency quantiles in milliseconds.\n");
+    out.push_str("# TYPE flake_latency_ms gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            for (quantile, value) in [
+                ("0.5", q.p50_latency_ms),
+                ("0.95", q.p95_latency_ms),
+                ("0.99", q.p99_latency_ms),
+            ] {
+                out.push_str(&format!(
+                    "flake_latency_ms{{{},query=\"{}\",quantile=\"{}\"}} {}\n",
+                    prometheus_target(report),
+                    prometheus_label(&q.query),
+                    quantile,
+                    value
+                ));
+            }
+        }
+    }
+
+    out.push_str("# HELP flake_latency_cv Coefficient of variation of request latency.\n");
+    out.push_str("# TYPE flake_latency_cv gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "flake_latency_cv{{{},query=\"{}\"}} {}\n",
+                prometheus_target(report),
+                prometheus_label(&q.query),
+                q.latency_cv
+            ));
+        }
+    }
+
+    out.push_str("# HELP flake_latency_mad_ms Median absolute deviation of request latency in milliseconds.\n");
+    out.push_str("# TYPE flake_latency_mad_ms gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            out.push_str(&format!(
+                "flake_latency_mad_ms{{{},query=\"{}\"}} {}\n",
+                prometheus_target(report),
+                prometheus_label(&q.query),
+                q.latency_mad_ms
+            ));
+        }
+    }
+
+    out.push_str("# HELP flake_apdex Apdex score against --apdex-target, from 0 to 1.\n");
+    out.push_str("# TYPE flake_apdex gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            if let Some(apdex) = q.apdex {
+                out.push_str(&format!(
+                    "flake_apdex{{{},query=\"{}\"}} {}\n",
+                    prometheus_target(report),
+                    prometheus_label(&q.query),
+                    apdex
+                ));
+            }
+        }
+    }
+
+    out.push_str("# HELP flake_ttfb_ms Time-to-first-byte quantiles in milliseconds.\n");
+    out.push_str("# TYPE flake_ttfb_ms gauge\n");
+    for report in reports {
+        for q in &report.queries {
+            for (quantile, value) in [
+                ("0.5", q.ttfb_p50_ms),
+                ("0.95", q.ttfb_p95_ms),
+                ("0.99", q.ttfb_p99_ms),
+            ] {
+                out.push_str(&format!(
+                    "flake_ttfb_ms{{{},query=\"{}\",quantile=\"{}\"}} {}\n",
+                    prometheus_target(report),
+                    prometheus_label(&q.query),
+                    quantile,
+                    value
+                ));
+            }
+        }
+    }
+    out
+}
+
+/// Escapes characters that would break a GitHub-flavored markdown table cell.
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+fn render_markdown(reports: &[EndpointReport]) -> String {
+    let mut out = String::from("## Flakiness Report\n\n");
+    out.push_str("| Status | Endpoint | Flakiness Score | Success Rate | Total Requests |\n");
+    out.push_str("|:---:|---|---:|---:|---:|\n");
+    for report in reports {
+        out.push_str(&format!(
+            "| {} | {} | {:.1} | {:.1}% | {} |\n",
+            get_status_emoji(report.flakiness_score),
+            markdown_cell(&endpoint_label(report)),
+            report.flakiness_score,
+            report.overall_success_rate * 100.0,
+            report.total_requests,
+        ));
+    }
+
+    for report in reports {
+        out.push_str(&format!("\n### {} {}\n\n", get_status_emoji(report.flakiness_score), markdown_cell(&endpoint_label(report))));
+        out.push_str("| Query | Success | Failure | Failure Rate | p50 (ms) | p95 (ms) | p99 (ms) |\n");
+        out.push_str("|---|---:|---:|---:|---:|---:|---:|\n");
+        for q in &report.queries {
+            out.push_str(&format!(
+                "| `{}` | {} | {} | {:.1}% | {:.1} | {:.1} | {:.1} |\n",
+                markdown_cell(&q.query),
+                q.success_count,
+                q.failure_count,
+                q.failure_rate * 100.0,
+                q.p50_latency_ms,
+                q.p95_latency_ms,
+                q.p99_latency_ms,
+            ));
+        }
+    }
+    out
+}
+
+/// Runs test cycles until Ctrl-C, then prints an aggregate over every cycle,
+/// including an interrupted last one. Returns the reports of the last cycle.
+async fn watch(args: &Args, config: &TestConfig, interval: Duration) -> Vec<EndpointReport> {
+    let mut cycles: Vec<Vec<EndpointReport>> = Vec::new();
+    let mut rotations: Vec<OutputRotation> =
+        args.outputs.iter().map(|(path, format)| OutputRotation::new(path, *format)).collect();
+    
+    loop {
+        say!("\n{} Watch cycle {}", "🔁".bright_blue(), cycles.len() + 1);
+        let mut run = run_report(config.clone()).await;
+        for report in &mut run.reports {
+            let previous = cycles
+                .last()
+                .and_then(|last| last.iter().find(|r| same_target(r, report)))
+                .and_then(|r| r.smoothed_score);
+            report.smoothed_score = Some(match previous {
+                Some(previous) => args.ema_alpha * report.flakiness_score + (1.0 - args.ema_alpha) * previous,
+                None => report.flakiness_score,
+            });
+        }
+        if run.interrupted {
+            print_interrupted();
+        }
+        print_results(args, &run);
+        for rotation in &mut rotations {
+            let format = rotation.format;
+            write_output_to(args, &run, rotation.next(args), format);
+        }
+        if let Some(webhook) = &args.alert_webhook {
+            send_alerts(webhook, args.alert_threshold, &run.reports).await;
+        }
+        if let Some(command) = &args.on_unhealthy {
+            for report in &run.reports {
+                let previous = cycles
+                    .last()
+                    .and_then(|last| last.iter().find(|r| same_target(r, report)))
+                    .and_then(|r| r.smoothed_score);
+                let score = report.smoothed_score.unwrap_or(report.flakiness_score);
+                if score > args.unhealthy_threshold && previous.is_none_or(|previous| previous <= args.unhealthy_threshold) {
+                    run_unhealthy_hook(command, report, score, args.unhealthy_threshold);
+                }
+            }
+        }
+        let interrupted = run.interrupted;
+        cycles.push(run.reports);
+        if interrupted {
+            break;
+        }
+        
+        tokio::select! {
+            _ = sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    
+    say!("\n{} Stopping watch mode", "⏹".bright_yellow());
+    print_watch_summary(&cycles);
+    cycles.pop().unwrap_or_default()
+}
+
+/// `--ramp` output: every step, and where each endpoint broke.
+#[derive(Debug, Serialize)]
+struct RampReport {
+    threshold: f64,
+    steps: Vec<RampStep>,
+    breaking_points: Vec<BreakingPoint>,
+}
+
+#[derive(Debug, Serialize)]
+struct RampStep {
+    concurrency: usize,
+    endpoint: String,
+    failure_rate: f64,
+    /// Worst p99 over the endpoint's queries.
+    p99_latency_ms: f64,
+    total_requests: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct BreakingPoint {
+    endpoint: String,
+    /// First concurrency whose failure rate exceeded the threshold; `None` if
+    /// the endpoint held up to the end of the ramp.
+    concurrency: Option<usize>,
+    /// Highest concurrency that stayed within the threshold.
+    last_good_concurrency: Option<usize>,
+}
+
+/// `--ramp`: one run per concurrency step, each `--duration` long. An endpoint
+/// is dropped from later steps once it breaks, so it is not pushed further
+/// past its limit. Returns each endpoint's report from the last step it ran.
+async fn ramp(args: &Args, config: &TestConfig) -> Vec<EndpointReport> {
+    let mut remaining = config.endpoints.clone();
+    let mut steps = Vec::new();
+    let mut breaking_points: Vec<BreakingPoint> = Vec::new();
+    let mut last_reports: Vec<EndpointReport> = Vec::new();
+    let mut concurrency = 1;
+
+    while concurrency <= args.concurrency && !remaining.is_empty() {
+        say!("\n{} Ramp step: concurrency {}", "📈".bright_blue(), concurrency);
+        let step = TestConfig {
+            endpoints: remaining.clone(),
+            concurrency,
+            // Every query ramps together.
+            query_concurrency: HashMap::new(),
+            ..config.clone()
+        };
+        let run = run_report(step).await;
+        for report in run.reports {
+            let label = endpoint_label(&report);
+            let broke = report.overall_failure_rate > args.ramp_threshold;
+            let point = match breaking_points.iter_mut().find(|point| point.endpoint == label) {
+                Some(point) => point,
+                None => {
+                    breaking_points.push(BreakingPoint { endpoint: label.clone(), concurrency: None, last_good_concurrency: None });
+                    breaking_points.last_mut().unwrap()
+                }
+            };
+            if broke {
+                point.concurrency = Some(concurrency);
+                remaining.retain(|endpoint| endpoint != &report.endpoint);
+            } else {
+                point.last_good_concurrency = Some(concurrency);
+            }
+            steps.push(RampStep {
+                concurrency,
+                endpoint: label,
+                failure_rate: report.overall_failure_rate,
+                p99_latency_ms: worst_p99(&report),
+                total_requests: report.total_requests,
+            });
+            last_reports.retain(|r| !same_target(r, &report));
+            last_reports.push(report);
+        }
+        if run.interrupted {
+            print_interrupted();
+            break;
+        }
+        concurrency += args.ramp_step;
+    }
+
+    let report = RampReport { threshold: args.ramp_threshold, steps, breaking_points };
+    print_ramp(&report);
+    for (path, _) in &args.outputs {
+        let contents = serde_json::to_string_pretty(&report).unwrap() + "\n";
+        let written = if is_stdout(path) {
+            std::io::stdout().lock().write_all(contents.as_bytes())
+        } else {
+            std::fs::write(path, contents)
+        };
+        match written {
+            Ok(_) if is_stdout(path) => {}
+            Ok(_) => say!("\n{} Ramp results exported to: {}", "💾".bright_green(), path.bright_cyan()),
+            Err(e) => eprintln!("\n{}Failed to write output: {}", icon("❌").bright_red(), e),
+        }
+    }
+    last_reports
+}
+
+fn print_ramp(report: &RampReport) {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", "           CONCURRENCY RAMP".bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("\n  {:>11}  {:>8}  {:>10}  {:>9}  Endpoint", "Concurrency", "Failures", "p99", "Requests");
+    for step in &report.steps {
+        let failures = format!("{:>7.1}%", step.failure_rate * 100.0);
+        let failures = if step.failure_rate > report.threshold { failures.bright_red() } else { failures.bright_green() };
+        say!("  {:>11}  {}  {:>8.1}ms  {:>9}  {}", step.concurrency, failures, step.p99_latency_ms,
+                 step.total_requests, step.endpoint.bright_cyan());
+    }
+    say!("\nBreaking points (failure rate above {}%):", report.threshold * 100.0);
+    for point in &report.breaking_points {
+        let last_good = point.last_good_concurrency.map_or("none".to_string(), |c| c.to_string());
+        match point.concurrency {
+            Some(concurrency) => say!("  {} {} broke at concurrency {} (last good: {})", "✗".bright_red(),
+                                          point.endpoint.bright_cyan(), concurrency, last_good),
+            None => say!("  {} {} held up to concurrency {}", "✓".bright_green(),
+                             point.endpoint.bright_cyan(), last_good),
+        }
+    }
+}
 
-    #[arg(short, long, value_delimiter = ',', 
-          default_value = "health,status,abci_info,net_info,genesis",
-          help = "Comma-separated list of RPC queries to test")]
-    queries: Vec<String>,
+/// `--on-unhealthy`: starts `command` under `sh -c` without waiting for it, and
+/// reports its exit status when it finishes. The endpoint comes from user input
+/// and may contain shell metacharacters, so it is only ever passed as an
+/// argument and in the environment, never as part of the command string.
+fn run_unhealthy_hook(command: &str, report: &EndpointReport, score: f64, threshold: f64) {
+    let endpoint = report.endpoint.clone();
+    let spawned = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("flake-detector")
+        .arg(&endpoint)
+        .arg(format!("{:.1}", score))
+        .env("FLAKE_ENDPOINT", &endpoint)
+        .env("FLAKE_SCORE", format!("{:.1}", score))
+        .env("FLAKE_RAW_SCORE", format!("{:.1}", report.flakiness_score))
+        .env("FLAKE_THRESHOLD", threshold.to_string())
+        .stdin(std::process::Stdio::null())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{}Failed to run --on-unhealthy for {}: {}", icon("⚠").bright_red(), endpoint, e);
+            return;
+        }
+    };
+    say!("{} {} crossed {} (score {:.1}): running --on-unhealthy", "🔧".bright_yellow(),
+             endpoint.bright_cyan(), threshold, score);
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if status.success() => {
+                say!("{} --on-unhealthy for {} finished", "✓".bright_green(), endpoint.bright_cyan());
+            }
+            Ok(status) => eprintln!("{}--on-unhealthy for {} exited with {}", icon("⚠").bright_red(), endpoint, status),
+            Err(e) => eprintln!("{}--on-unhealthy for {} failed: {}", icon("⚠").bright_red(), endpoint, e),
+        }
+    });
+}
 
-    #[arg(short, long, help = "Output JSON file path (optional)")]
-    output: Option<String>,
+/// Prints the `--check` reachability table; returns whether every request succeeded.
+fn print_check_results(results: &[CheckResult]) -> bool {
+    let endpoint_width = results.iter().map(|r| r.endpoint.len()).max().unwrap_or(0).max("Endpoint".len());
+    let query_width = results.iter().map(|r| r.query.len()).max().unwrap_or(0).max("Query".len());
+    say!("\n  {:<ew$}  {:<qw$}  Result", "Endpoint", "Query", ew = endpoint_width, qw = query_width);
+    for result in results {
+        let outcome = match &result.outcome {
+            Ok(latency) => format!("OK ({:.0}ms)", latency.as_secs_f64() * 1000.0).bright_green(),
+            Err(reason) => reason.bright_red(),
+        };
+        say!("  {:<ew$}  {:<qw$}  {}", result.endpoint, result.query, outcome,
+                 ew = endpoint_width, qw = query_width);
+    }
+    let failed = results.iter().filter(|r| r.outcome.is_err()).count();
+    if failed == 0 {
+        say!("\n{} All {} checks passed", "✅".bright_green(), results.len());
+    } else {
+        say!("\n{} {} of {} checks failed", "❌".bright_red(), failed, results.len());
+    }
+    failed == 0
+}
 
-    #[arg(short = 'c', long, default_value = "10", help = "Concurrent requests per endpoint")]
-    concurrency: usize,
+/// The `scan` subcommand: one `health` request per endpoint, then exits with
+/// status 1 if any endpoint is down.
+async fn run_scan(scan: ScanArgs) {
+    let (endpoints, _) = collect_endpoints(&scan.endpoints, scan.endpoints_file.as_deref());
+    if endpoints.is_empty() {
+        exit_with_error("No endpoints to scan: pass --endpoints or --endpoints-file");
+    }
+    let config = TestConfig {
+        endpoints,
+        queries: vec!["health".to_string()],
+        timeout: Duration::from_secs(scan.timeout),
+        headers: scan.headers.into_iter().fold(HeaderMap::new(), |mut headers, (name, value)| {
+            headers.insert(name, value);
+            headers
+        }),
+        ..TestConfig::default()
+    };
+    if let Err(e) = config.validate() {
+        exit_with_error(&e);
+    }
+    say!("{} Scanning {} endpoint(s)", "🔎".bright_blue(), config.endpoints.len());
+    let up = print_scan_results(&check_endpoints(&config).await);
+    std::process::exit(if up { 0 } else { 1 });
+}
 
-    #[arg(short = 't', long, default_value = "5", help = "Request timeout in seconds")]
-    timeout: u64,
+/// Prints the `scan` table; returns whether every endpoint is up.
+fn print_scan_results(results: &[CheckResult]) -> bool {
+    let endpoint_width = results.iter().map(|r| r.endpoint.len()).max().unwrap_or(0).max("Endpoint".len());
+    say!("\n  {:<ew$}  Status", "Endpoint", ew = endpoint_width);
+    for result in results {
+        let status = match &result.outcome {
+            Ok(latency) => format!("UP    {:.0}ms", latency.as_secs_f64() * 1000.0).bright_green(),
+            Err(reason) => format!("DOWN  {}", reason).bright_red(),
+        };
+        say!("  {:<ew$}  {}", result.endpoint, status, ew = endpoint_width);
+    }
+    let down = results.iter().filter(|r| r.outcome.is_err()).count();
+    if down == 0 {
+        say!("\n{} All {} endpoints up", "✅".bright_green(), results.len());
+    } else {
+        say!("\n{} {} of {} endpoints down", "❌".bright_red(), down, results.len());
+    }
+    down == 0
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct QueryResult {
-    query: String,
-    success_count: u64,
-    failure_count: u64,
-    total_requests: u64,
-    failure_rate: f64,
-    p50_latency_ms: f64,
-    p95_latency_ms: f64,
-    p99_latency_ms: f64,
-    avg_latency_ms: f64,
-    min_latency_ms: f64,
-    max_latency_ms: f64,
+fn print_interrupted() {
+    say!("\n{} Run interrupted: reporting partial results", "⏹".bright_yellow());
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct EndpointReport {
-    endpoint: String,
-    overall_success_rate: f64,
-    overall_failure_rate: f64,
-    flakiness_score: f64,
-    total_requests: u64,
-    test_duration_secs: u64,
-    queries: Vec<QueryResult>,
+/// On the first Ctrl-C, stops the run so the partial results are still reported;
+/// a second Ctrl-C quits immediately.
+fn handle_ctrl_c(config: &TestConfig) {
+    let stop = config.stop.clone();
+    let progress = config.progress.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        stop.store(true, Ordering::Relaxed);
+        let notice = || eprintln!("\n{} Stopping after in-flight requests (Ctrl-C again to quit)",
+                                  "⏹".bright_yellow());
+        match &progress {
+            _ if quiet() => {}
+            Some(progress) => progress.suspend(notice),
+            None => notice(),
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
+
+/// Loads a previous JSON report for `--baseline`.
+fn load_baseline(path: &str) -> Vec<EndpointReport> {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to read baseline {}: {}", path, e)));
+    // Reports written before the run wrapper existed are a bare array.
+    let parsed = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(&contents)
+    } else {
+        serde_json::from_str::<RunReport>(&contents).map(|run| run.reports)
+    };
+    parsed.unwrap_or_else(|e| exit_with_error(&format!("Failed to parse baseline {}: {}", path, e)))
 }
 
-#[derive(Debug)]
-struct TestMetrics {
-    success_count: u64,
-    failure_count: u64,
-    latencies: Histogram<u64>,
+/// Limits one endpoint must stay within; unset ones are not checked.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Thresholds {
+    max_score: Option<f64>,
+    /// Applies to the slowest p99 among the queries the endpoint answered.
+    max_p99_ms: Option<f64>,
+    /// A fraction, like `overall_success_rate`.
+    min_success_rate: Option<f64>,
 }
 
-impl TestMetrics {
-    fn new() -> Self {
-        Self {
-            success_count: 0,
-            failure_count: 0,
-            latencies: Histogram::<u64>::new(3).unwrap(),
+impl Thresholds {
+    /// These limits, with any unset one taken from `defaults`.
+    fn or(self, defaults: Thresholds) -> Thresholds {
+        Thresholds {
+            max_score: self.max_score.or(defaults.max_score),
+            max_p99_ms: self.max_p99_ms.or(defaults.max_p99_ms),
+            min_success_rate: self.min_success_rate.or(defaults.min_success_rate),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_score.is_none() && self.max_p99_ms.is_none() && self.min_success_rate.is_none()
+    }
+
+    /// One line per limit `report` is outside of.
+    fn violations(&self, report: &EndpointReport) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(max) = self.max_score
+            && report.flakiness_score > max
+        {
+            lines.push(format!("score {:.1} > {:.1}", report.flakiness_score, max));
         }
+        let slowest = report
+            .queries
+            .iter()
+            .filter(|q| !q.unsupported && q.success_count > 0)
+            .max_by(|a, b| a.p99_latency_ms.total_cmp(&b.p99_latency_ms));
+        if let (Some(max), Some(q)) = (self.max_p99_ms, slowest)
+            && q.p99_latency_ms > max
+        {
+            lines.push(format!("{} p99 {:.1}ms > {:.0}ms", q.query, q.p99_latency_ms, max));
+        }
+        if let Some(min) = self.min_success_rate
+            && report.overall_success_rate < min
+        {
+            lines.push(format!("success rate {:.1}% < {:.1}%", report.overall_success_rate * 100.0, min * 100.0));
+        }
+        lines
     }
 }
 
-async fn test_endpoint_query(
-    client: &Client,
-    endpoint: &str,
-    query: &str,
-) -> Result<Duration, String> {
-    let url = format!("{}/{}", endpoint.trim_end_matches('/'), query);
-    let start = Instant::now();
-    
-    match client.get(&url).send().await {
-        Ok(response) => {
-            let elapsed = start.elapsed();
-            if response.status().is_success() {
-                Ok(elapsed)
-            } else {
-                Err(format!("HTTP {}", response.status()))
-            }
+/// A `--thresholds` file. `defaults` cover every endpoint; an entry under
+/// `endpoints`, keyed by URL, overrides them limit by limit.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThresholdFile {
+    #[serde(default)]
+    defaults: Thresholds,
+    #[serde(default)]
+    endpoints: HashMap<String, Thresholds>,
+}
+
+/// Loads and checks a `--thresholds` file before the run, so a typo fails fast.
+fn load_thresholds(path: &str) -> ThresholdFile {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to read thresholds {}: {}", path, e)));
+    let file: ThresholdFile = serde_json::from_str(&contents)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to parse thresholds {}: {}", path, e)));
+    let named = file.endpoints.iter().map(|(endpoint, limits)| (endpoint.as_str(), limits));
+    for (name, limits) in std::iter::once(("defaults", &file.defaults)).chain(named) {
+        if limits.max_score.is_some_and(|max| max < 0.0) || limits.max_p99_ms.is_some_and(|max| max < 0.0) {
+            exit_with_error(&format!("Thresholds for {} in {} must not be negative", name, path));
+        }
+        if limits.min_success_rate.is_some_and(|min| !(0.0..=1.0).contains(&min)) {
+            exit_with_error(&format!("min_success_rate for {} in {} must be between 0 and 1", name, path));
         }
-        Err(e) => Err(format!("Request failed: {}", e)),
     }
+    file
 }
 
-async fn run_continuous_tests(
-    client: Client,
-    endpoint: String,
-    query: String,
-    duration: Duration,
-    metrics: Arc<Mutex<TestMetrics>>,
-) {
-    let end_time = Instant::now() + duration;
-    
-    while Instant::now() < end_time {
-        match test_endpoint_query(&client, &endpoint, &query).await {
-            Ok(latency) => {
-                let mut m = metrics.lock().await;
-                m.success_count += 1;
-                let _ = m.latencies.record(latency.as_micros() as u64);
+/// Prints pass/fail for every endpoint against its own thresholds. Returns the
+/// number of endpoints that failed.
+fn print_threshold_results(file: &ThresholdFile, reports: &[EndpointReport]) -> usize {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", "      THRESHOLDS".bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
+
+    let mut failed = 0;
+    for report in reports {
+        let own = file.endpoints.get(&report.endpoint);
+        let limits = own.copied().unwrap_or_default().or(file.defaults);
+        let source = if own.is_some() { "" } else { " (defaults)" };
+        if limits.is_empty() {
+            say!("\n- {} - no thresholds set", endpoint_label(report).bright_cyan());
+            continue;
+        }
+        let violations = limits.violations(report);
+        if violations.is_empty() {
+            say!("\n{} {} - pass{}", "✓".bright_green(), endpoint_label(report).bright_cyan(), source);
+        } else {
+            failed += 1;
+            say!("\n{} {} - fail{}:", "✗".bright_red(), endpoint_label(report).bright_cyan(), source);
+            for line in violations {
+                say!("    {}", line.bright_red());
             }
-            Err(_) => {
-                let mut m = metrics.lock().await;
-                m.failure_count += 1;
+        }
+    }
+    let mut unknown: Vec<&String> =
+        file.endpoints.keys().filter(|endpoint| !reports.iter().any(|r| &r.endpoint == *endpoint)).collect();
+    unknown.sort();
+    for endpoint in unknown {
+        say!("\n{} {} - has thresholds but was not tested", "-".bright_yellow(), endpoint.bright_yellow());
+    }
+    failed
+}
+
+/// Successful and failed requests of one endpoint across `--uptime-from`
+/// history, keyed like `same_target`.
+#[derive(Debug, Default)]
+struct Uptime {
+    runs: HashSet<String>,
+    success: u64,
+    failure: u64,
+}
+
+impl Uptime {
+    /// Unsupported queries say nothing about availability, as in the score.
+    fn add(&mut self, report: &EndpointReport) {
+        for q in report.queries.iter().filter(|q| !q.unsupported) {
+            self.success += q.success_count;
+            self.failure += q.failure_count;
+        }
+    }
+
+    fn percent(&self) -> Option<f64> {
+        let answered = self.success + self.failure;
+        (answered > 0).then(|| self.success as f64 / answered as f64 * 100.0)
+    }
+}
+
+/// Reads `--append` lines for `--uptime-from`. Read before the run, so a
+/// history that is also this run's `--output` is not counted twice. Lines that
+/// do not parse, like one cut short by a killed run, are skipped with a warning.
+fn load_uptime_history(path: &str) -> HashMap<(String, IpVersion), Uptime> {
+    // Not `#[serde(flatten)]` as in `render_ndjson`: flattening cannot read
+    // back the integer keys of `status_code_breakdown`.
+    fn parse(line: &str) -> Option<(String, EndpointReport)> {
+        let mut value: Value = serde_json::from_str(line).ok()?;
+        let run_timestamp = value.as_object_mut()?.remove("run_timestamp")?;
+        Some((run_timestamp.as_str()?.to_string(), serde_json::from_value(value).ok()?))
+    }
+
+    let mut history: HashMap<(String, IpVersion), Uptime> = HashMap::new();
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return history,
+        Err(e) => exit_with_error(&format!("Failed to read uptime history {}: {}", path, e)),
+    };
+    let mut skipped = 0;
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match parse(line) {
+            Some((run_timestamp, report)) => {
+                let uptime = history.entry((report.endpoint.clone(), report.ip_version)).or_default();
+                uptime.runs.insert(run_timestamp);
+                uptime.add(&report);
             }
+            None => skipped += 1,
         }
-        
-        sleep(Duration::from_millis(100)).await;
     }
+    if skipped > 0 {
+        eprintln!("{}Skipped {} unreadable line(s) in {}", icon("⚠").bright_yellow(), skipped, path);
+    }
+    history
 }
 
-fn calculate_flakiness_score(failure_rate: f64, p99_latency_ms: f64) -> f64 {
-    let latency_threshold = 1000.0;
-    let latency_severity = (p99_latency_ms / latency_threshold).min(1.0);
-    
-    let score = (failure_rate * 0.7) + (latency_severity * 0.3);
-    (score * 100.0).min(100.0)
+/// Prints each endpoint's uptime over its history plus this run, next to this run's score.
+fn print_uptime(mut history: HashMap<(String, IpVersion), Uptime>, reports: &[EndpointReport]) {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", "              LIFETIME UPTIME".bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
+
+    for report in reports {
+        let mut uptime = history.remove(&(report.endpoint.clone(), report.ip_version)).unwrap_or_default();
+        let past_runs = uptime.runs.len();
+        uptime.add(report);
+        let percent = uptime.percent().map_or("n/a".to_string(), |percent| format!("{:.3}%", percent));
+        say!("\n{} {}", get_status_emoji(report.flakiness_score), endpoint_label(report).bright_cyan());
+        say!("  Uptime: {} over {} run(s), {} requests | Current Score: {:.1}/100",
+                 percent.bright_white(),
+                 past_runs + 1,
+                 uptime.success + uptime.failure,
+                 report.flakiness_score);
+    }
 }
 
-fn get_status_emoji(score: f64) -> &'static str {
-    if score < 10.0 {
-        "🟢"
-    } else if score < 30.0 {
-        "🟡"
-    } else if score < 60.0 {
-        "🟠"
+/// Percentage growth from `old` to `new`. Any growth from zero is infinite.
+fn growth_pct(old: f64, new: f64) -> f64 {
+    if old > 0.0 {
+        (new - old) / old * 100.0
+    } else if new > 0.0 {
+        f64::INFINITY
     } else {
-        "🔴"
+        0.0
     }
 }
 
-async fn test_endpoint(
-    endpoint: &str,
-    queries: &[String],
-    duration: u64,
-    concurrency: usize,
-    timeout: u64,
-) -> EndpointReport {
-    println!("\n{} Testing endpoint: {}", "🔍".bright_blue(), endpoint.bright_cyan());
-    
-    let client = Client::builder()
-        .timeout(Duration::from_secs(timeout))
-        .build()
-        .unwrap();
-    
-    let test_duration = Duration::from_secs(duration);
-    let mut query_results = Vec::new();
-    let mut total_success = 0u64;
-    let mut total_failure = 0u64;
-    
-    for query in queries {
-        println!("  {} Testing query: {}", "→".bright_white(), query.bright_white());
-        
-        let metrics = Arc::new(Mutex::new(TestMetrics::new()));
-        let mut tasks = vec![];
-        
-        for _ in 0..concurrency {
-            let client_clone = client.clone();
-            let endpoint_clone = endpoint.to_string();
-            let query_clone = query.to_string();
-            let metrics_clone = metrics.clone();
-            
-            tasks.push(tokio::spawn(run_continuous_tests(
-                client_clone,
-                endpoint_clone,
-                query_clone,
-                test_duration,
-                metrics_clone,
-            )));
+fn format_change(old: f64, new: f64, unit: &str) -> String {
+    let growth = growth_pct(old, new);
+    let pct = if growth.is_infinite() { "from 0".to_string() } else { format!("{:+.0}%", growth) };
+    format!("{:.1}{unit} → {:.1}{unit} ({})", old, new, pct, unit = unit)
+}
+
+/// Prints this run against `baseline`, matching endpoints and then queries by
+/// name. Returns the number of regressed scores and p99 latencies.
+fn print_baseline_comparison(baseline: &[EndpointReport], reports: &[EndpointReport], threshold_pct: f64) -> usize {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", format!("      BASELINE COMPARISON (threshold +{}%)", threshold_pct).bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
+
+    let mut regressions = 0;
+    for report in reports {
+        let Some(old) = baseline.iter().find(|b| same_target(b, report)) else {
+            say!("\n{} {} - new endpoint (not in baseline)", "+".bright_cyan(), endpoint_label(report).bright_cyan());
+            continue;
+        };
+
+        let mut lines = Vec::new();
+        let mut notes = Vec::new();
+        if growth_pct(old.flakiness_score, report.flakiness_score) > threshold_pct {
+            lines.push(format!("  score {}", format_change(old.flakiness_score, report.flakiness_score, "")));
         }
-        
-        for task in tasks {
-            let _ = task.await;
+        for query in &report.queries {
+            match old.queries.iter().find(|q| q.query == query.query) {
+                Some(old_query) if growth_pct(old_query.p99_latency_ms, query.p99_latency_ms) > threshold_pct => {
+                    lines.push(format!("  {} p99 {}", query.query,
+                                       format_change(old_query.p99_latency_ms, query.p99_latency_ms, "ms")));
+                }
+                Some(_) => {}
+                None => notes.push(format!("  {} query {} is new (not in baseline)", "+".bright_cyan(), query.query)),
+            }
         }
-        
-        let final_metrics = metrics.lock().await;
-        let success = final_metrics.success_count;
-        let failure = final_metrics.failure_count;
-        let total = success + failure;
-        
-        total_success += success;
-        total_failure += failure;
-        
-        let failure_rate = if total > 0 {
-            failure as f64 / total as f64
-        } else {
-            0.0
-        };
-        
-        let (p50, p95, p99, avg, min, max) = if !final_metrics.latencies.is_empty() {
-            (
-                final_metrics.latencies.value_at_quantile(0.5) as f64 / 1000.0,
-                final_metrics.latencies.value_at_quantile(0.95) as f64 / 1000.0,
-                final_metrics.latencies.value_at_quantile(0.99) as f64 / 1000.0,
-                final_metrics.latencies.mean() / 1000.0,
-                final_metrics.latencies.min() as f64 / 1000.0,
-                final_metrics.latencies.max() as f64 / 1000.0,
-            )
+        for old_query in &old.queries {
+            if !report.queries.iter().any(|q| q.query == old_query.query) {
+                notes.push(format!("  {} query {} is missing from this run", "-".bright_yellow(), old_query.query));
+            }
+        }
+
+        if lines.is_empty() {
+            say!("\n{} {} - no regressions (score {})", "✓".bright_green(), endpoint_label(report).bright_cyan(),
+                     format_change(old.flakiness_score, report.flakiness_score, ""));
         } else {
-            (0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
-        };
-        
-        println!("    ✓ Success: {} | ✗ Failure: {} | Rate: {:.1}%", 
-                 success.to_string().bright_green(),
-                 failure.to_string().bright_red(),
-                 (failure_rate * 100.0).to_string().bright_yellow());
-        println!("    Latency: p50={:.1}ms p95={:.1}ms p99={:.1}ms", p50, p95, p99);
-        
-        query_results.push(QueryResult {
-            query: query.clone(),
-            success_count: success,
-            failure_count: failure,
-            total_requests: total,
-            failure_rate,
-            p50_latency_ms: p50,
-            p95_latency_ms: p95,
-            p99_latency_ms: p99,
-            avg_latency_ms: avg,
-            min_latency_ms: min,
-            max_latency_ms: max,
-        });
+            regressions += lines.len();
+            say!("\n{} {} - regressed:", "⚠".bright_red(), endpoint_label(report).bright_cyan());
+            for line in lines {
+                say!("  {}", line.bright_red());
+            }
+        }
+        for note in notes {
+            say!("{}", note);
+        }
+    }
+    for old in baseline {
+        if !reports.iter().any(|r| same_target(r, old)) {
+            say!("\n{} {} - in baseline but missing from this run", "-".bright_yellow(), endpoint_label(old).bright_yellow());
+        }
     }
+    regressions
+}
+
+/// Whether two reports are for the same endpoint over the same address family.
+fn same_target(a: &EndpointReport, b: &EndpointReport) -> bool {
+    a.endpoint == b.endpoint && a.ip_version == b.ip_version
+}
+
+fn print_watch_summary(cycles: &[Vec<EndpointReport>]) {
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+    say!("{}", format!("      AGGREGATE SUMMARY ({} cycles)", cycles.len()).bright_white().bold());
+    say!("{}", "═══════════════════════════════════════════════════".bright_blue());
     
-    let total_requests = total_success + total_failure;
-    let overall_failure_rate = if total_requests > 0 {
-        total_failure as f64 / total_requests as f64
-    } else {
-        0.0
+    let Some(first) = cycles.first() else {
+        say!("\n  No cycle completed.");
+        return;
     };
     
-    let avg_p99 = query_results.iter()
-        .map(|r| r.p99_latency_ms)
-        .sum::<f64>() / query_results.len() as f64;
-    
-    let flakiness_score = calculate_flakiness_score(overall_failure_rate, avg_p99);
-    
-    EndpointReport {
-        endpoint: endpoint.to_string(),
-        overall_success_rate: 1.0 - overall_failure_rate,
-        overall_failure_rate,
-        flakiness_score,
-        total_requests,
-        test_duration_secs: duration,
-        queries: query_results,
+    for target in first {
+        let history: Vec<&EndpointReport> = cycles
+            .iter()
+            .flatten()
+            .filter(|r| same_target(r, target))
+            .collect();
+        let mean_score = history.iter().map(|r| r.flakiness_score).sum::<f64>() / history.len() as f64;
+        let worst_score = history.iter().map(|r| r.flakiness_score).fold(0.0, f64::max);
+        let total_requests: u64 = history.iter().map(|r| r.total_requests).sum();
+        let total_success: u64 = history
+            .iter()
+            .flat_map(|r| &r.queries)
+            .map(|q| q.success_count)
+            .sum();
+        let success_rate = if total_requests > 0 {
+            total_success as f64 / total_requests as f64
+        } else {
+            0.0
+        };
+        
+        say!("\n{} {} - Mean Score: {:.1}/100 (worst {:.1})",
+                 get_status_emoji(mean_score),
+                 endpoint_label(target).bright_cyan(),
+                 mean_score,
+                 worst_score);
+        say!("  Success Rate: {:.1}% | Total Requests: {}", success_rate * 100.0, total_requests);
+        if let Some(smoothed) = history.last().and_then(|r| r.smoothed_score) {
+            say!("  Final Smoothed Score: {:.1}/100", smoothed);
+        }
     }
+    
+    say!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
+}
+
+/// Body POSTed to `--alert-webhook`.
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    endpoint: &'a str,
+    flakiness_score: f64,
+    /// Set in `--watch` mode, where it is the score compared to the threshold.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    smoothed_score: Option<f64>,
+    threshold: f64,
+    overall_failure_rate: f64,
+    failures_by_kind: HashMap<String, u64>,
+    queries: Vec<QueryAlert<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryAlert<'a> {
+    query: &'a str,
+    failure_rate: f64,
+    failures_by_kind: &'a HashMap<String, u64>,
 }
 
-fn print_summary(reports: &[EndpointReport]) {
-    println!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
-    println!("{}", "           FLAKINESS DETECTION SUMMARY".bright_white().bold());
-    println!("{}", "═══════════════════════════════════════════════════".bright_blue());
+/// Fires one webhook per endpoint above the threshold. Delivery problems are
+/// reported on stderr and never abort the run.
+async fn send_alerts(webhook: &Url, threshold: f64, reports: &[EndpointReport]) {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
     
-    for report in reports {
-        let emoji = get_status_emoji(report.flakiness_score);
-        let score_color = if report.flakiness_score < 10.0 {
-            report.flakiness_score.to_string().bright_green()
-        } else if report.flakiness_score < 30.0 {
-            report.flakiness_score.to_string().bright_yellow()
-        } else if report.flakiness_score < 60.0 {
-            report.flakiness_score.to_string().bright_magenta()
-        } else {
-            report.flakiness_score.to_string().bright_red()
+    for report in reports.iter().filter(|r| r.smoothed_score.unwrap_or(r.flakiness_score) > threshold) {
+        let mut failures_by_kind = HashMap::new();
+        for (kind, count) in report.queries.iter().flat_map(|q| &q.failures_by_kind) {
+            *failures_by_kind.entry(kind.clone()).or_insert(0) += count;
+        }
+        let payload = AlertPayload {
+            endpoint: &report.endpoint,
+            flakiness_score: report.flakiness_score,
+            smoothed_score: report.smoothed_score,
+            threshold,
+            overall_failure_rate: report.overall_failure_rate,
+            failures_by_kind,
+            queries: report.queries
+                .iter()
+                .map(|q| QueryAlert {
+                    query: &q.query,
+                    failure_rate: q.failure_rate,
+                    failures_by_kind: &q.failures_by_kind,
+                })
+                .collect(),
         };
         
-        println!("\n{} {} - Flakiness Score: {}/100",
-                 emoji,
-                 report.endpoint.bright_cyan(),
-                 score_color.bold());
-        println!("  Success Rate: {:.1}% | Total Requests: {}",
-                 (report.overall_success_rate * 100.0).to_string().bright_green(),
-                 report.total_requests);
+        let delivery = client.post(webhook.clone()).json(&payload).send().await;
+        match delivery.and_then(|response| response.error_for_status()) {
+            Ok(_) => say!("{} Alert sent for {}", "📣".bright_yellow(), report.endpoint.bright_cyan()),
+            Err(e) => eprintln!("{}Failed to deliver alert for {}: {}", icon("⚠").bright_red(), report.endpoint, e),
+        }
+    }
+}
+
+/// `--append`: one line per endpoint report, tagged with when the run started.
+fn render_ndjson(run: &RunReport) -> String {
+    #[derive(Serialize)]
+    struct Line<'a> {
+        run_timestamp: String,
+        #[serde(flatten)]
+        report: &'a EndpointReport,
+    }
+
+    let run_timestamp = run.run_started_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut out = String::new();
+    for report in &run.reports {
+        out.push_str(&serde_json::to_string(&Line { run_timestamp: run_timestamp.clone(), report }).unwrap());
+        out.push('\n');
+    }
+    out
+}
+
+/// `--output -` sends the report to stdout, and main turns on `--quiet` so
+/// nothing else is printed there.
+fn is_stdout(path: &str) -> bool {
+    path == "-"
+}
+
+fn write_output(args: &Args, run: &RunReport) {
+    for (output_path, format) in &args.outputs {
+        write_output_to(args, run, output_path, *format);
+    }
+}
+
+/// `--rotate-bytes` / `--rotate-cycles`: the `--output` file watch cycles are
+/// currently appended to. Every append opens and closes the file, so nothing
+/// is left buffered in the old file when a new one starts.
+struct OutputRotation {
+    /// The `--output` path rotated files are named after.
+    base: String,
+    format: OutputFormat,
+    path: String,
+    cycles: u32,
+}
+
+impl OutputRotation {
+    fn new(path: &str, format: OutputFormat) -> Self {
+        Self { base: path.to_string(), format, path: path.to_string(), cycles: 0 }
+    }
+
+    /// The file to append this cycle to, switching to a new one if the
+    /// current one is full.
+    fn next(&mut self, args: &Args) -> &str {
+        let full_by_cycles = args.rotate_cycles.is_some_and(|max| self.cycles >= max);
+        let full_by_size = args.rotate_bytes.is_some_and(|max| {
+            std::fs::metadata(&self.path).is_ok_and(|file| file.len() >= max)
+        });
+        if full_by_cycles || full_by_size {
+            self.path = rotated_path(&self.base);
+            self.cycles = 0;
+            say!("{} Rotating output to {}", "🗂".bright_blue(), self.path.bright_cyan());
+        }
+        self.cycles += 1;
+        &self.path
+    }
+}
+
+/// `results.ndjson` becomes `results-20240101T120000Z.ndjson`, with a counter
+/// added if that name is already taken.
+fn rotated_path(base: &str) -> String {
+    let path = std::path::Path::new(base);
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or(base);
+    let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| format!(".{}", ext)).unwrap_or_default();
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let name = |suffix: String| path.with_file_name(format!("{}-{}{}{}", stem, timestamp, suffix, extension));
+    let mut candidate = name(String::new());
+    let mut counter = 1;
+    while candidate.exists() {
+        counter += 1;
+        candidate = name(format!("-{}", counter));
+    }
+    candidate.to_string_lossy().into_owned()
+}
+
+fn write_output_to(args: &Args, run: &RunReport, output_path: &str, format: OutputFormat) {
+    let contents = if args.append {
+        render_ndjson(run)
+    } else {
+        match format {
+            OutputFormat::Json => serde_json::to_string_pretty(run).unwrap() + "\n",
+            OutputFormat::Csv => render_csv(&run.reports),
+            OutputFormat::Prometheus => render_prometheus(&run.reports),
+            OutputFormat::Markdown => render_markdown(&run.reports),
+        }
+    };
+    let written = if is_stdout(output_path) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(contents.as_bytes()).and_then(|_| stdout.flush())
+    } else if args.append {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output_path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+    } else {
+        std::fs::write(output_path, contents)
+    };
+    match written {
+        Ok(_) if is_stdout(output_path) => {}
+        Ok(_) => say!("\n{} Results exported to: {}", 
+                        "💾".bright_green(), 
+                        output_path.bright_cyan()),
+        Err(e) => eprintln!("\n{}Failed to write output: {}", 
+                           icon("❌").bright_red(), e),
     }
-    
-    println!("\n{}", "═══════════════════════════════════════════════════".bright_blue());
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (mut args, matches) = match cli.command {
+        Some(Command::Scan(scan)) => return run_scan(scan).await,
+        Some(Command::Test(args)) => (*args, matches.subcommand_matches("test").cloned().unwrap_or_default()),
+        None => (cli.test, matches),
+    };
+    if args.quiet {
+        QUIET.store(true, Ordering::Relaxed);
+    }
+    if !use_color(&args) {
+        colored::control::set_override(false);
+    }
+    init_logging(&args);
+    if let Some(profile) = args.profile {
+        apply_profile(&mut args, &matches, profile);
+    }
+    if let Some(path) = args.config.clone() {
+        apply_config_file(&mut args, &matches, &path);
+    }
+    if args.deterministic {
+        apply_deterministic(&mut args);
+    }
+    if args.output.iter().any(|path| is_stdout(path)) {
+        args.quiet = true;
+        QUIET.store(true, Ordering::Relaxed);
+    }
+    (args.endpoints, args.regions) = collect_endpoints(&args.endpoints, args.endpoints_file.as_deref());
+    if args.endpoints.is_empty() {
+        exit_with_error("No endpoints to test: pass --endpoints, --endpoints-file or a config file");
+    }
+    // The built-in query list is Tendermint RPC; gRPC and TCP modes have their own defaults.
+    if args.grpc && args.queries.join(",") == DEFAULT_QUERIES {
+        args.queries = GRPC_DEFAULT_QUERIES.iter().map(|q| q.to_string()).collect();
+    }
+    if args.tcp_only && args.queries.join(",") == DEFAULT_QUERIES {
+        args.queries = TCP_DEFAULT_QUERIES.iter().map(|q| q.to_string()).collect();
+    }
+    args.queries = args.queries
+        .iter()
+        .map(|q| q.trim().to_string())
+        .filter(|q| !q.is_empty())
+        .collect();
+    for (name, _) in &args.payload_file {
+        if !args.queries.contains(name) {
+            args.queries.push(name.clone());
+        }
+    }
+    
+    if let Some((endpoint, _)) = args.exclude.iter().find(|(endpoint, _)| {
+        !args.endpoints.iter().any(|e| e.trim_end_matches('/') == endpoint.trim_end_matches('/'))
+    }) {
+        exit_with_error(&format!("--exclude names {}, which is not one of the endpoints being tested", endpoint));
+    }
+    if args.apdex_target == Some(0) {
+        exit_with_error("--apdex-target must be at least 1ms");
+    }
+    if args.slow_factor < 1.0 {
+        exit_with_error(&format!("--slow-factor must be at least 1, got {}", args.slow_factor));
+    }
+    if !(args.ema_alpha > 0.0 && args.ema_alpha <= 1.0) {
+        exit_with_error(&format!("--ema-alpha must be above 0 and at most 1, got {}", args.ema_alpha));
+    }
+    if args.ramp && args.ramp_step == 0 {
+        exit_with_error("--ramp-step must be at least 1");
+    }
+    if args.ramp && !(0.0..1.0).contains(&args.ramp_threshold) {
+        exit_with_error(&format!("--ramp-threshold must be at least 0 and below 1, got {}", args.ramp_threshold));
+    }
+    let explicit_format = matches.value_source("format") == Some(ValueSource::CommandLine);
+    let several = args.output.len() > 1;
+    if args.output.iter().filter(|path| is_stdout(path)).count() > 1 {
+        exit_with_error("--output - can only be given once");
+    }
+    args.outputs = args.output
+        .iter()
+        .map(|path| match output_format(path, args.format, explicit_format, several) {
+            Ok(format) => (path.clone(), format),
+            Err(e) => exit_with_error(&e),
+        })
+        .collect();
+    if args.ramp && args.outputs.iter().any(|(_, format)| *format != OutputFormat::Json) {
+        exit_with_error("--ramp writes its steps as JSON and needs JSON --output files");
+    }
+    if (args.rotate_bytes.is_some() || args.rotate_cycles.is_some()) && args.output.iter().any(|path| is_stdout(path)) {
+        exit_with_error("--rotate-bytes and --rotate-cycles need an --output file, not stdout");
+    }
+    if args.rotate_bytes == Some(0) || args.rotate_cycles == Some(0) {
+        exit_with_error("--rotate-bytes and --rotate-cycles must be at least 1");
+    }
+    if args.append && args.outputs.iter().any(|(_, format)| *format != OutputFormat::Json) {
+        exit_with_error("--append writes newline-delimited JSON and needs JSON --output files");
+    }
+    let mut config = TestConfig::from(&args);
+    if let Err(e) = config.validate() {
+        exit_with_error(&e);
+    }
+    if args.check {
+        say!("{} Checking {} endpoint(s) × {} query(ies)", "🔎".bright_blue(),
+                 config.endpoints.len(), config.queries.len());
+        let passed = print_check_results(&check_endpoints(&config).await);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if args.preflight || args.skip_unreachable {
+        let unreachable = preflight(&config).await;
+        if !unreachable.is_empty() && !args.skip_unreachable {
+            let endpoints: Vec<String> =
+                unreachable.iter().map(|skipped| format!("{} ({})", skipped.endpoint, skipped.reason)).collect();
+            exit_with_error(&format!("Unreachable endpoint(s): {}. Pass --skip-unreachable to test the rest",
+                                     endpoints.join(", ")));
+        }
+        config.endpoints.retain(|endpoint| !unreachable.iter().any(|skipped| &skipped.endpoint == endpoint));
+        if config.endpoints.is_empty() {
+            exit_with_error("No reachable endpoints to test");
+        }
+        config.skipped_endpoints = unreachable;
+    }
+    let baseline = args.baseline.as_deref().map(load_baseline);
+    let thresholds = args.thresholds.as_deref().map(load_thresholds);
+    let uptime_history = args.uptime_from.as_deref().map(load_uptime_history);
+    handle_ctrl_c(&config);
     
-    println!("{}", "╔══════════════════════════════════════════════════╗".bright_blue());
-    println!("{}", "║     COSMOS RPC FLAKE DETECTOR v0.1.0             ║".bright_white().bold());
-    println!("{}", "╚══════════════════════════════════════════════════╝".bright_blue());
+    say!("{}", "╔══════════════════════════════════════════════════╗".bright_blue());
+    say!("{}", "║     COSMOS RPC FLAKE DETECTOR v0.1.0             ║".bright_white().bold());
+    say!("{}", "╚══════════════════════════════════════════════════╝".bright_blue());
     
-    println!("\n{} Configuration:", "⚙".bright_yellow());
-    println!("  Endpoints: {}", args.endpoints.len());
-    println!("  Test Duration: {}s", args.duration);
-    println!("  Queries: {}", args.queries.join(", "));
-    println!("  Concurrency: {}", args.concurrency);
+    say!("\n{} Configuration:", "⚙".bright_yellow());
+    say!("  Endpoints: {}", config.endpoints.len());
+    if !config.skipped_endpoints.is_empty() {
+        say!("  Skipped (unreachable): {}", config.skipped_endpoints.len());
+    }
+    match args.requests {
+        Some(requests) if args.deterministic => say!("  Requests per Query: {} (deterministic)", requests),
+        Some(requests) => say!("  Requests per Query: {}", requests),
+        None => say!("  Test Duration: {}s", args.duration),
+    }
+    if args.warmup > 0 {
+        say!("  Warmup: {}s", args.warmup);
+    }
+    if args.query_cooldown > 0 {
+        say!("  Query Cooldown: {}ms", args.query_cooldown);
+    }
+    let scheduling = if args.interleave {
+        " (interleaved)"
+    } else if args.parallel_queries {
+        " (parallel)"
+    } else {
+        ""
+    };
+    say!("  Queries: {}{}", args.queries.join(", "), scheduling);
+    say!("  Concurrency: {}", args.concurrency);
+    if !args.query_concurrency.is_empty() {
+        let overrides: Vec<String> = args.query_concurrency.iter().map(|(q, n)| format!("{}={}", q, n)).collect();
+        say!("  Query Concurrency: {}", overrides.join(", "));
+    }
+    if let Some(max) = args.max_concurrency_global {
+        say!("  Global Concurrency Cap: {}", max);
+    }
+    if let Some(ms) = args.slow_request_ms {
+        say!("  Slow Request Alert: >{}ms", ms);
+    }
+    for (endpoint, queries) in &args.exclude {
+        say!("  Excluded: {} on {}", queries.join(", "), endpoint);
+    }
+    if args.tcp_only {
+        say!("  Protocol: TCP connect only");
+    } else if args.grpc {
+        say!("  Protocol: gRPC");
+    } else {
+        say!("  Protocol: {:?}", args.protocol);
+    }
+    if args.method != RequestMethod::Get {
+        say!("  Method: {:?}", args.method);
+    }
+    if !args.query_params.is_empty() {
+        let params: Vec<String> = args.query_params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        say!("  Query Params: {}", params.join("&"));
+    }
+    if let Some(batch) = args.batch {
+        say!("  Batch Size: {}", batch);
+    }
+    say!("  Request Interval: {}ms", args.interval);
+    say!("  Retries: {}", args.retries);
+    if args.retries > 0 {
+        say!("  Retry Budget: {}% of answered requests", args.retry_budget * 100.0);
+    }
+    if config.jitter > 0.0 {
+        say!("  Jitter: ±{:.0}% (seed {})", config.jitter * 100.0, config.seed);
+    }
+    if config.sample_rate < 1.0 {
+        say!("  Sample Rate: {:.1}% of scheduled requests (seed {})", config.sample_rate * 100.0, config.seed);
+    }
+    say!("  Mode: {}", if args.sequential { "sequential" } else { "concurrent" });
+    if args.stop_on_healthy {
+        say!("  Stop on Healthy: after {} requests at ≥{}% success",
+                 args.healthy_min_requests, args.healthy_success_rate * 100.0);
+    }
+    if args.adaptive {
+        say!("  Adaptive Concurrency: back off above {}% failures", args.adaptive_threshold * 100.0);
+    }
+    if !args.grpc && !args.tcp_only {
+        say!("  Connections: {}", if args.no_keepalive { "fresh per request" } else { "keep-alive (pooled)" });
+        if args.follow_redirects {
+            say!("  Redirects: followed, up to {} hops", args.max_redirects);
+        } else {
+            say!("  Redirects: not followed (3xx counts as a failure)");
+        }
+    }
+    if !args.headers.is_empty() {
+        // Values may carry API keys, so only the names are echoed.
+        let names: Vec<&str> = args.headers.iter().map(|(name, _)| name.as_str()).collect();
+        say!("  Headers: {}", names.join(", "));
+    }
+    if args.bearer.is_some() {
+        say!("  Auth: Bearer ********");
+    } else if args.basic_auth.is_some() {
+        say!("  Auth: Basic ********");
+    }
+    if let Some(proxy) = &args.proxy {
+        // Leave out any credentials in the URL.
+        say!("  Proxy: {}://{}:{}", proxy.scheme(), proxy.host_str().unwrap_or_default(),
+                 proxy.port_or_known_default().unwrap_or(1080));
+    }
+    if let Some(path) = &args.ca_cert {
+        say!("  CA Certificate: {}", path);
+    }
+    if args.ip_version != IpVersion::Auto {
+        say!("  IP Version: {}", args.ip_version.as_str());
+    }
+    if args.parallel_queries && args.queries.len() > 1 {
+        let workers: usize = args.queries
+            .iter()
+            .map(|query| {
+                args.query_concurrency
+                    .iter()
+                    .find(|(name, _)| name == query)
+                    .map_or(args.concurrency, |(_, n)| *n)
+            })
+            .sum();
+        eprintln!("\n{}--parallel-queries runs {} queries at once: up to {} concurrent requests per endpoint. \
+                   Lower --concurrency if that is more than the endpoint should take.",
+                  icon("⚠").bright_yellow(), args.queries.len(), workers);
+    }
+    if args.insecure {
+        eprintln!("\n{}{}", icon("⚠").bright_red(),
+                  "WARNING: --insecure disables TLS certificate verification. Any server, including an \
+                   impostor, is accepted as the endpoint.".bright_red().bold());
+    }
     
-    let mut reports = Vec::new();
+    let reports = match args.watch {
+        Some(interval) => watch(&args, &config, Duration::from_secs(interval)).await,
+        None if args.ramp => ramp(&args, &config).await,
+        None => {
+            let run = run_report(config.clone()).await;
+            if run.interrupted {
+                print_interrupted();
+            }
+            print_results(&args, &run);
+            write_output(&args, &run);
+            if let Some(webhook) = &args.alert_webhook {
+                send_alerts(webhook, args.alert_threshold, &run.reports).await;
+            }
+            run.reports
+        }
+    };
     
-    for endpoint in &args.endpoints {
-        let report = test_endpoint(
-            endpoint,
-            &args.queries,
-            args.duration,
-            args.concurrency,
-            args.timeout,
-        ).await;
-        reports.push(report);
+    let regressions = baseline
+        .map(|baseline| print_baseline_comparison(&baseline, &reports, args.regression_threshold))
+        .unwrap_or(0);
+    if let Some(history) = uptime_history {
+        print_uptime(history, &reports);
     }
+    let threshold_failures = thresholds
+        .map(|thresholds| print_threshold_results(&thresholds, &reports))
+        .unwrap_or(0);
     
-    print_summary(&reports);
+    say!("\n{} Testing complete!\n", "✅".bright_green());
     
-    if let Some(output_path) = args.output {
-        match std::fs::write(&output_path, serde_json::to_string_pretty(&reports).unwrap()) {
-            Ok(_) => println!("\n{} Results exported to: {}", 
-                            "💾".bright_green(), 
-                            output_path.bright_cyan()),
-            Err(e) => eprintln!("\n{} Failed to write output: {}", 
-                               "❌".bright_red(), e),
+    if let Some(threshold) = args.fail_threshold {
+        let tripped: Vec<&EndpointReport> = reports
+            .iter()
+            .filter(|r| r.flakiness_score > threshold)
+            .collect();
+        if !tripped.is_empty() {
+            eprintln!("{}{} endpoint(s) exceeded the flakiness threshold of {}:",
+                      icon("❌").bright_red(), tripped.len(), threshold);
+            for report in tripped {
+                eprintln!("  {} - score {:.1}", endpoint_label(report), report.flakiness_score);
+            }
+            std::process::exit(1);
         }
     }
-    
-    println!("\n{} Testing complete!\n", "✅".bright_green());
+    if args.fail_on_slo {
+        let violations = reports
+            .iter()
+            .flat_map(|r| &r.queries)
+            .filter(|q| q.slo_met == Some(false))
+            .count();
+        if violations > 0 {
+            eprintln!("{}{} query(ies) violated their p99 SLO", icon("❌").bright_red(), violations);
+            std::process::exit(1);
+        }
+    }
+    if regressions > 0 {
+        eprintln!("{}{} regression(s) against the baseline", icon("❌").bright_red(), regressions);
+        std::process::exit(1);
+    }
+    if threshold_failures > 0 {
+        eprintln!("{}{} endpoint(s) failed their thresholds", icon("❌").bright_red(), threshold_failures);
+        std::process::exit(1);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,56 @@
+//! Probes for `--tcp-only` mode: a bare TCP handshake with no HTTP on top, to
+//! tell network reachability apart from application-layer problems. The only
+//! query is `connect`.
+
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+use crate::{FailureKind, IpVersion, QueryResponse};
+
+/// Used instead of the RPC defaults when `--queries` is not given.
+pub const DEFAULT_QUERIES: &[&str] = &["connect"];
+
+pub fn validate_query(query: &str) -> Result<(), String> {
+    match query {
+        "connect" => Ok(()),
+        other => Err(format!("Unsupported TCP query \"{}\": --tcp-only only supports connect", other)),
+    }
+}
+
+/// Host and port to connect to: `host:port`, or a URL, whose scheme supplies
+/// the port if it has none (`https://host` is port 443).
+pub fn target(endpoint: &str) -> Result<(String, u16), String> {
+    let invalid = |reason: &str| format!("Invalid TCP endpoint {}: {}", endpoint, reason);
+    let url = if endpoint.contains("://") {
+        Url::parse(endpoint)
+    } else {
+        Url::parse(&format!("tcp://{}", endpoint))
+    }
+    .map_err(|e| invalid(&e.to_string()))?;
+    // An IPv6 literal keeps its brackets in the URL, which the resolver rejects.
+    let host = url.host_str().ok_or_else(|| invalid("no host"))?.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().ok_or_else(|| invalid("no port, use host:port"))?;
+    Ok((host.to_string(), port))
+}
+
+/// Resolves `host` and times the TCP handshake alone, so latency and TTFB are
+/// the connect time. The connection is closed straight away.
+pub async fn probe(host: &str, port: u16, ip_version: IpVersion, limit: Duration) -> Result<QueryResponse, FailureKind> {
+    let address = timeout(limit, lookup_host((host, port)))
+        .await
+        .map_err(|_| FailureKind::Timeout)?
+        .map_err(|_| FailureKind::Connect)?
+        .find(|address| ip_version.allows(address))
+        .ok_or(FailureKind::Connect)?;
+
+    let start = Instant::now();
+    timeout(limit, TcpStream::connect(address))
+        .await
+        .map_err(|_| FailureKind::Timeout)?
+        .map_err(|_| FailureKind::Connect)?;
+    let latency = start.elapsed();
+    Ok(QueryResponse { latency, ttfb: latency, status: None, body: None, bytes: 0, retried: false, redirect: None })
+}
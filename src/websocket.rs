@@ -0,0 +1,193 @@
+//! `--websocket`: subscribes to `NewBlock` events on the Tendermint `/websocket`
+//! endpoint and tracks how reliably blocks arrive. Subscriptions fail differently
+//! from plain HTTP: connections drop silently and events go missing.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use hdrhistogram::Histogram;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time::{sleep, timeout};
+use tokio_native_tls::native_tls::TlsConnector;
+use tokio_tungstenite::{connect_async_tls_with_config, Connector};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{LiveCounters, STOP_POLL};
+
+const SUBSCRIBE_NEW_BLOCK: &str =
+    r#"{"jsonrpc":"2.0","method":"subscribe","id":1,"params":{"query":"tm.event='NewBlock'"}}"#;
+
+/// Pause before reconnecting after a failed connection attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketReport {
+    pub url: String,
+    pub blocks_received: u64,
+    /// Heights skipped between consecutive `NewBlock` events, including across reconnects.
+    pub missed_blocks: u64,
+    /// Established connections that closed or errored before the test ended.
+    pub connection_drops: u64,
+    pub reconnections: u64,
+    pub failed_connects: u64,
+    pub avg_block_gap_ms: f64,
+    pub p99_block_gap_ms: f64,
+    pub max_block_gap_ms: f64,
+}
+
+/// `ws(s)://<host>/websocket` for an `http(s)://<host>` RPC endpoint.
+pub fn websocket_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = if let Some(rest) = endpoint.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = endpoint.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        endpoint.to_string()
+    };
+    if url.ends_with("/websocket") {
+        url
+    } else {
+        format!("{}/websocket", url)
+    }
+}
+
+struct Subscription {
+    blocks_received: u64,
+    missed_blocks: u64,
+    connection_drops: u64,
+    reconnections: u64,
+    failed_connects: u64,
+    last_height: Option<u64>,
+    last_block_at: Option<Instant>,
+    gaps: Histogram<u64>,
+}
+
+impl Subscription {
+    fn record_block(&mut self, height: u64) {
+        let now = Instant::now();
+        self.blocks_received += 1;
+        if let Some(last) = self.last_height {
+            self.missed_blocks += height.saturating_sub(last + 1);
+        }
+        if let Some(at) = self.last_block_at {
+            let _ = self.gaps.record(now.duration_since(at).as_micros() as u64);
+        }
+        self.last_height = Some(self.last_height.map_or(height, |last| last.max(height)));
+        self.last_block_at = Some(now);
+    }
+}
+
+/// Keeps a `NewBlock` subscription open for `duration`, reconnecting whenever it
+/// drops or `stop` is set. Blocks count as successes on `live`, drops and failed
+/// connects as failures. `wss://` connections use `tls`, or the system roots when `None`.
+pub async fn monitor(
+    endpoint: &str,
+    headers: &HeaderMap,
+    tls: Option<TlsConnector>,
+    duration: Duration,
+    connect_timeout: Duration,
+    live: &LiveCounters,
+    stop: &AtomicBool,
+) -> Result<WebSocketReport, String> {
+    let url = websocket_url(endpoint);
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| format!("Invalid WebSocket URL {}: {}", url, e))?;
+    request.headers_mut().extend(headers.clone());
+
+    let mut sub = Subscription {
+        blocks_received: 0,
+        missed_blocks: 0,
+        connection_drops: 0,
+        reconnections: 0,
+        failed_connects: 0,
+        last_height: None,
+        last_block_at: None,
+        gaps: Histogram::<u64>::new(3).unwrap(),
+    };
+    let deadline = Instant::now() + duration;
+    let mut connected_before = false;
+    let running = || Instant::now() < deadline && !stop.load(Ordering::Relaxed);
+
+    while running() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let connect = connect_async_tls_with_config(request.clone(), None, false, tls.clone().map(Connector::NativeTls));
+        let mut socket = match timeout(connect_timeout.min(remaining), connect).await {
+            Ok(Ok((socket, _))) => socket,
+            _ => {
+                sub.failed_connects += 1;
+                live.failure.fetch_add(1, Ordering::Relaxed);
+                sleep(RECONNECT_DELAY.min(deadline.saturating_duration_since(Instant::now()))).await;
+                continue;
+            }
+        };
+        if connected_before {
+            sub.reconnections += 1;
+        }
+        connected_before = true;
+
+        if socket.send(Message::text(SUBSCRIBE_NEW_BLOCK)).await.is_err() {
+            sub.connection_drops += 1;
+            live.failure.fetch_add(1, Ordering::Relaxed);
+            sleep(RECONNECT_DELAY.min(deadline.saturating_duration_since(Instant::now()))).await;
+            continue;
+        }
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining.min(STOP_POLL), socket.next()).await {
+                Err(_) if running() => {}
+                Err(_) => {
+                    let _ = socket.close(None).await;
+                    break;
+                }
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    if let Some(height) = new_block_height(&text) {
+                        sub.record_block(height);
+                        live.success.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => {
+                    sub.connection_drops += 1;
+                    live.failure.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                Ok(Some(Ok(_))) => {}
+            }
+        }
+    }
+
+    let (avg, p99, max) = if sub.gaps.is_empty() {
+        (0.0, 0.0, 0.0)
+    } else {
+        (
+            sub.gaps.mean() / 1000.0,
+            sub.gaps.value_at_quantile(0.99) as f64 / 1000.0,
+            sub.gaps.max() as f64 / 1000.0,
+        )
+    };
+    Ok(WebSocketReport {
+        url,
+        blocks_received: sub.blocks_received,
+        missed_blocks: sub.missed_blocks,
+        connection_drops: sub.connection_drops,
+        reconnections: sub.reconnections,
+        failed_connects: sub.failed_connects,
+        avg_block_gap_ms: avg,
+        p99_block_gap_ms: p99,
+        max_block_gap_ms: max,
+    })
+}
+
+/// The height of a `NewBlock` event; `None` for the subscription ack and other messages.
+fn new_block_height(text: &str) -> Option<u64> {
+    let event: Value = serde_json::from_str(text).ok()?;
+    let height = event.pointer("/result/data/value/block/header/height")?;
+    height.as_u64().or_else(|| height.as_str()?.parse().ok())
+}